@@ -0,0 +1,260 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::ops::Range;
+
+use glyph_parts::{builtin_table, PartTable};
+use glyphcode::GlyphCode;
+use screen::{Attributes, CursorShape};
+
+/// The built-in glyph parts. Each part is 20x40 pixels, stored as 40 rows of
+/// 3 bytes with the leftmost pixel in the least significant bit. This is the
+/// layout `XCreateBitmapFromData` expects, so the parts stack vertically into
+/// one tall bitmap.
+static BUILTIN_PARTS: &[u8] = include_bytes!("glyphs.bin");
+
+pub const BUILTIN_PART_WIDTH: u32 = 20;
+pub const BUILTIN_PART_HEIGHT: u32 = 40;
+
+/// A set of same-sized 1-bit bitmaps that glyphs are drawn from, and the
+/// table saying which of them each glyph code is drawn with.
+///
+/// Part `n` occupies rows `n*part_height` through `(n+1)*part_height - 1` of
+/// the atlas. Atlases for other fonts can be loaded with the `font` module.
+#[derive(Clone, PartialEq, Eq)]
+pub struct GlyphAtlas {
+    part_width: u32,
+    part_height: u32,
+    bits: Cow<'static, [u8]>,
+    table: Cow<'static, PartTable>,
+}
+
+impl GlyphAtlas {
+    pub fn builtin() -> GlyphAtlas {
+        GlyphAtlas {
+            part_width: BUILTIN_PART_WIDTH,
+            part_height: BUILTIN_PART_HEIGHT,
+            bits: Cow::Borrowed(BUILTIN_PARTS),
+            table: Cow::Borrowed(builtin_table()),
+        }
+    }
+
+    /// An atlas of parts laid out like the built-in one. Panics if `bits`
+    /// isn't a whole number of parts.
+    pub fn new(part_width: u32, part_height: u32, bits: Vec<u8>, table: PartTable) -> GlyphAtlas {
+        let part_bytes = part_width.div_ceil(8) as usize * part_height as usize;
+        assert!(part_bytes > 0, "parts must not be empty");
+        assert_eq!(bits.len() % part_bytes, 0, "atlas is not a whole number of {}x{} parts", part_width, part_height);
+        GlyphAtlas {
+            part_width: part_width,
+            part_height: part_height,
+            bits: Cow::Owned(bits),
+            table: Cow::Owned(table),
+        }
+    }
+
+    /// A copy with every pixel enlarged to a `factor` by `factor` square.
+    pub fn scaled(&self, factor: u32) -> GlyphAtlas {
+        if factor == 1 {
+            return self.clone();
+        }
+
+        let part_width = self.part_width * factor;
+        let part_height = self.part_height * factor;
+        let row_bytes = part_width.div_ceil(8) as usize;
+        let mut bits = vec![0u8; row_bytes * (part_height * self.part_count()) as usize];
+        for part in 0..self.part_count() {
+            for y in 0..part_height {
+                let row_start = ((part * part_height + y) as usize) * row_bytes;
+                for x in 0..part_width {
+                    if self.is_set(part, x / factor, y / factor) {
+                        bits[row_start + (x / 8) as usize] |= 1 << (x % 8);
+                    }
+                }
+            }
+        }
+
+        GlyphAtlas {
+            part_width: part_width,
+            part_height: part_height,
+            bits: Cow::Owned(bits),
+            table: self.table.clone(),
+        }
+    }
+
+    pub fn part_width(&self) -> u32 {
+        self.part_width
+    }
+
+    pub fn part_height(&self) -> u32 {
+        self.part_height
+    }
+
+    /// Number of bytes in one row of a part. Rows are padded to a whole byte.
+    pub fn row_bytes(&self) -> usize {
+        self.part_width.div_ceil(8) as usize
+    }
+
+    pub fn part_bytes(&self) -> usize {
+        self.row_bytes() * self.part_height as usize
+    }
+
+    pub fn part_count(&self) -> u32 {
+        (self.bits.len() / self.part_bytes()) as u32
+    }
+
+    /// The raw bits of every part, in the layout described on `BUILTIN_PARTS`.
+    pub fn bits(&self) -> &[u8] {
+        &self.bits[..]
+    }
+
+    /// Whether pixel (x, y) of `part` is set. Pixels of parts past the end of
+    /// the atlas are never set.
+    pub fn is_set(&self, part: u32, x: u32, y: u32) -> bool {
+        if part >= self.part_count() || x >= self.part_width || y >= self.part_height {
+            return false;
+        }
+
+        let row_start = part as usize * self.part_bytes() + y as usize * self.row_bytes();
+        let byte = self.bits[row_start + (x / 8) as usize];
+        (byte >> (x % 8)) & 1 != 0
+    }
+
+    /// How far right bold glyphs are drawn a second time.
+    pub fn bold_offset(&self) -> u32 {
+        (self.part_width / 20).max(1)
+    }
+
+    /// How far right row `y` of an italic glyph is shifted: a fifth of the
+    /// width at the top, nothing at the bottom.
+    pub fn italic_shift(&self, y: u32) -> u32 {
+        let from_bottom = self.part_height - 1 - y.min(self.part_height - 1);
+        from_bottom * (self.part_width / 5) / self.part_height
+    }
+
+    /// Thickness of underlines and strikethroughs.
+    fn line_thickness(&self) -> u32 {
+        (self.part_height / 20).max(1)
+    }
+
+    /// The rows an underline covers, below the baseline of the built-in
+    /// glyphs.
+    pub fn underline_rows(&self) -> Range<u32> {
+        let top = (self.part_height * 33 / 40).min(self.part_height - self.line_thickness());
+        top..top + self.line_thickness()
+    }
+
+    /// The rows a strikethrough covers, across the middle.
+    pub fn strikethrough_rows(&self) -> Range<u32> {
+        let top = self.part_height / 2;
+        top..(top + self.line_thickness()).min(self.part_height)
+    }
+
+    /// The rows of each line `attributes` calls for: an underline, a
+    /// strikethrough, both or neither.
+    pub fn lines(&self, attributes: Attributes) -> Vec<Range<u32>> {
+        let mut lines = Vec::new();
+        if attributes.contains(Attributes::UNDERLINE) {
+            lines.push(self.underline_rows());
+        }
+        if attributes.contains(Attributes::STRIKETHROUGH) {
+            lines.push(self.strikethrough_rows());
+        }
+        lines
+    }
+
+    /// The (columns, rows) an underline or bar cursor covers. A block
+    /// cursor inverts the whole cell instead, so it has none.
+    pub fn cursor_rect(&self, shape: CursorShape) -> Option<(Range<u32>, Range<u32>)> {
+        match shape {
+            CursorShape::Block => None,
+            CursorShape::Underline => {
+                let top = self.part_height.saturating_sub(2 * self.line_thickness());
+                Some((0..self.part_width, top..self.part_height))
+            }
+            CursorShape::Bar => Some((0..(self.part_width / 10).max(1), 0..self.part_height)),
+        }
+    }
+
+    pub fn table(&self) -> &PartTable {
+        &self.table
+    }
+
+    /// The table, for adding glyph codes of your own.
+    pub fn table_mut(&mut self) -> &mut PartTable {
+        self.table.to_mut()
+    }
+
+    /// The parts to draw for `glyph`, in order.
+    pub fn parts(&self, glyph: GlyphCode) -> Vec<u32> {
+        self.table.parts(glyph)
+    }
+}
+
+impl fmt::Debug for GlyphAtlas {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("GlyphAtlas")
+            .field("part_width", &self.part_width)
+            .field("part_height", &self.part_height)
+            .field("part_count", &self.part_count())
+            .field("glyphs", &self.table.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GlyphAtlas;
+    use screen::CursorShape;
+
+    #[test]
+    fn builtin_is_whole_parts() {
+        let atlas = GlyphAtlas::builtin();
+        assert_eq!(atlas.bits().len() % atlas.part_bytes(), 0);
+        assert_eq!(atlas.part_count(), 243);
+    }
+
+    #[test]
+    fn scaling() {
+        let atlas = GlyphAtlas::builtin();
+        let scaled = atlas.scaled(3);
+        assert_eq!((scaled.part_width(), scaled.part_height()), (60, 120));
+        assert_eq!(scaled.part_count(), atlas.part_count());
+        for &(x, y) in &[(2, 2), (10, 20), (19, 39)] {
+            for &(dx, dy) in &[(0, 0), (2, 2)] {
+                assert_eq!(scaled.is_set(0, x*3 + dx, y*3 + dy), atlas.is_set(0, x, y));
+            }
+        }
+    }
+
+    #[test]
+    fn decorations() {
+        let atlas = GlyphAtlas::builtin();
+        assert_eq!(atlas.bold_offset(), 1);
+        assert_eq!((atlas.italic_shift(0), atlas.italic_shift(30), atlas.italic_shift(39)), (3, 0, 0));
+        assert_eq!(atlas.underline_rows(), 33..35);
+        assert_eq!(atlas.strikethrough_rows(), 20..22);
+
+        let scaled = atlas.scaled(2);
+        assert_eq!(scaled.bold_offset(), 2);
+        assert_eq!(scaled.italic_shift(0), 7);
+        assert_eq!(scaled.underline_rows(), 66..70);
+    }
+
+    #[test]
+    fn cursor_rects() {
+        let atlas = GlyphAtlas::builtin();
+        assert_eq!(atlas.cursor_rect(CursorShape::Block), None);
+        assert_eq!(atlas.cursor_rect(CursorShape::Underline), Some((0..20, 36..40)));
+        assert_eq!(atlas.cursor_rect(CursorShape::Bar), Some((0..2, 0..40)));
+    }
+
+    #[test]
+    fn frame_part() {
+        // Part 0 is the frame of the missing-character box.
+        let atlas = GlyphAtlas::builtin();
+        assert!(atlas.is_set(0, 2, 2));
+        assert!(atlas.is_set(0, 17, 39));
+        assert!(!atlas.is_set(0, 10, 20));
+        assert!(!atlas.is_set(atlas.part_count(), 2, 2));
+    }
+}
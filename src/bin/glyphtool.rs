@@ -0,0 +1,456 @@
+//! Inspects and edits glyph atlases like `src/glyphs.bin`.
+//!
+//! An atlas is a run of same-sized 1-bit parts, each stored as rows padded to
+//! whole bytes with the leftmost pixel in the least significant bit. The
+//! built-in parts are 20x40, so 3 bytes a row and 120 bytes a part.
+//!
+//! Images are PBM files: P1 (plain text, handy for small edits by hand) or
+//! P4 (binary). Black pixels are ink.
+
+extern crate gridui;
+
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process;
+
+use gridui::atlas::{BUILTIN_PART_HEIGHT, BUILTIN_PART_WIDTH};
+
+const USAGE: &str = "usage: glyphtool [--size WxH] <atlas> <command> [args]
+
+commands:
+  check                      report the part size and count, fail if the
+                             atlas isn't a whole number of parts
+  sheet <out.pbm> [columns]  draw every part, labeled with its index
+  extract <part> <out.pbm>   write one part as an image
+  replace <part> <in.pbm>    overwrite one part, or append it if <part> is
+                             the part count
+  split <dir>                write every part to <dir>/NNN.pbm
+  build <in.pbm>...          rebuild the atlas from images, one per part";
+
+/// 3x5 digits for sheet labels, one row per string.
+const LABEL_DIGITS: [[&str; 5]; 10] = [
+    ["###", "#.#", "#.#", "#.#", "###"],
+    [".#.", "##.", ".#.", ".#.", "###"],
+    ["###", "..#", "###", "#..", "###"],
+    ["###", "..#", ".##", "..#", "###"],
+    ["#.#", "#.#", "###", "..#", "..#"],
+    ["###", "#..", "###", "..#", "###"],
+    ["###", "#..", "###", "#.#", "###"],
+    ["###", "..#", ".#.", ".#.", ".#."],
+    ["###", "#.#", "###", "#.#", "###"],
+    ["###", "#.#", "###", "..#", "###"],
+];
+const LABEL_HEIGHT: u32 = 7;
+
+/// A 1-bit image, true for ink.
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Bitmap {
+    width: u32,
+    height: u32,
+    pixels: Vec<bool>,
+}
+
+impl Bitmap {
+    fn new(width: u32, height: u32) -> Bitmap {
+        Bitmap { width: width, height: height, pixels: vec![false; (width * height) as usize] }
+    }
+
+    fn get(&self, x: u32, y: u32) -> bool {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    fn set(&mut self, x: u32, y: u32, ink: bool) {
+        if x < self.width && y < self.height {
+            self.pixels[(y * self.width + x) as usize] = ink;
+        }
+    }
+}
+
+/// The raw contents of an atlas file.
+struct Atlas {
+    part_width: u32,
+    part_height: u32,
+    bits: Vec<u8>,
+}
+
+impl Atlas {
+    fn row_bytes(&self) -> usize {
+        self.part_width.div_ceil(8) as usize
+    }
+
+    fn part_bytes(&self) -> usize {
+        self.row_bytes() * self.part_height as usize
+    }
+
+    fn part_count(&self) -> u32 {
+        (self.bits.len() / self.part_bytes()) as u32
+    }
+
+    /// Bytes after the last whole part.
+    fn leftover(&self) -> usize {
+        self.bits.len() % self.part_bytes()
+    }
+
+    fn part(&self, part: u32) -> Bitmap {
+        let mut bitmap = Bitmap::new(self.part_width, self.part_height);
+        let start = part as usize * self.part_bytes();
+        for y in 0..self.part_height {
+            let row = &self.bits[start + y as usize * self.row_bytes()..];
+            for x in 0..self.part_width {
+                bitmap.set(x, y, row[(x / 8) as usize] & (1 << (x % 8)) != 0);
+            }
+        }
+        bitmap
+    }
+
+    fn encode(&self, bitmap: &Bitmap) -> Result<Vec<u8>, String> {
+        if (bitmap.width, bitmap.height) != (self.part_width, self.part_height) {
+            return Err(format!("image is {}x{}, parts are {}x{}",
+                               bitmap.width, bitmap.height, self.part_width, self.part_height));
+        }
+        let mut bytes = vec![0u8; self.part_bytes()];
+        for y in 0..self.part_height {
+            for x in 0..self.part_width {
+                if bitmap.get(x, y) {
+                    bytes[y as usize * self.row_bytes() + (x / 8) as usize] |= 1 << (x % 8);
+                }
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn set_part(&mut self, part: u32, bitmap: &Bitmap) -> Result<(), String> {
+        let bytes = self.encode(bitmap)?;
+        let start = part as usize * self.part_bytes();
+        if part == self.part_count() {
+            self.bits.truncate(start);
+            self.bits.extend(bytes);
+        } else if part < self.part_count() {
+            self.bits[start..start + bytes.len()].copy_from_slice(&bytes);
+        } else {
+            return Err(format!("part {} is past the end of the atlas ({} parts)", part, self.part_count()));
+        }
+        Ok(())
+    }
+}
+
+/// Draws every part in a grid with `columns` columns, each labeled with
+/// its index and separated by 1-pixel lines.
+fn contact_sheet(atlas: &Atlas, columns: u32) -> Bitmap {
+    let cell_width = atlas.part_width + 1;
+    let cell_height = LABEL_HEIGHT + atlas.part_height + 1;
+    let rows = atlas.part_count().div_ceil(columns).max(1);
+    let mut sheet = Bitmap::new(columns * cell_width + 1, rows * cell_height + 1);
+
+    for x in 0..sheet.width {
+        for row in 0..=rows {
+            sheet.set(x, row * cell_height, true);
+        }
+    }
+    for y in 0..sheet.height {
+        for col in 0..=columns {
+            sheet.set(col * cell_width, y, true);
+        }
+    }
+
+    for part in 0..atlas.part_count() {
+        let left = (part % columns) * cell_width + 1;
+        let top = (part / columns) * cell_height + 1;
+        // Labels wider than narrow parts are cut off at the cell's edge.
+        for (i, digit) in part.to_string().bytes().enumerate() {
+            for (y, pattern) in LABEL_DIGITS[(digit - b'0') as usize].iter().enumerate() {
+                for (x, pixel) in pattern.bytes().enumerate() {
+                    let x = 1 + i as u32 * 4 + x as u32;
+                    if x < atlas.part_width {
+                        sheet.set(left + x, top + y as u32, pixel == b'#');
+                    }
+                }
+            }
+        }
+        for x in 0..atlas.part_width {
+            sheet.set(left + x, top + LABEL_HEIGHT - 1, true);
+        }
+
+        let bitmap = atlas.part(part);
+        for y in 0..atlas.part_height {
+            for x in 0..atlas.part_width {
+                sheet.set(left + x, top + LABEL_HEIGHT + y, bitmap.get(x, y));
+            }
+        }
+    }
+    sheet
+}
+
+fn write_pbm_plain(bitmap: &Bitmap) -> Vec<u8> {
+    let mut out = format!("P1\n{} {}\n", bitmap.width, bitmap.height);
+    for y in 0..bitmap.height {
+        for x in 0..bitmap.width {
+            out.push(if bitmap.get(x, y) { '1' } else { '0' });
+        }
+        out.push('\n');
+    }
+    out.into_bytes()
+}
+
+fn write_pbm_raw(bitmap: &Bitmap) -> Vec<u8> {
+    let mut out = Vec::new();
+    write!(out, "P4\n{} {}\n", bitmap.width, bitmap.height).unwrap();
+    let row_bytes = bitmap.width.div_ceil(8) as usize;
+    for y in 0..bitmap.height {
+        let mut row = vec![0u8; row_bytes];
+        for x in 0..bitmap.width {
+            if bitmap.get(x, y) {
+                row[(x / 8) as usize] |= 0x80 >> (x % 8);
+            }
+        }
+        out.extend(row);
+    }
+    out
+}
+
+/// Splits a PBM into its header fields and the data after them.
+fn pbm_header(data: &[u8], fields: usize) -> Result<(Vec<u32>, &[u8]), String> {
+    let mut values = Vec::new();
+    let mut pos = 2;
+    while values.len() < fields {
+        match data.get(pos) {
+            Some(b'#') => {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            Some(c) if c.is_ascii_whitespace() => { pos += 1; }
+            Some(c) if c.is_ascii_digit() => {
+                let start = pos;
+                while pos < data.len() && data[pos].is_ascii_digit() {
+                    pos += 1;
+                }
+                let value = std::str::from_utf8(&data[start..pos]).unwrap().parse()
+                    .map_err(|_| "PBM dimension too large".to_string())?;
+                values.push(value);
+            }
+            _ => { return Err("malformed PBM header".to_string()); }
+        }
+    }
+    // One whitespace character separates the header from the pixels.
+    Ok((values, data.get(pos + 1..).unwrap_or(&[])))
+}
+
+fn read_pbm(data: &[u8]) -> Result<Bitmap, String> {
+    let raw = if data.starts_with(b"P4") {
+        true
+    } else if data.starts_with(b"P1") {
+        false
+    } else {
+        return Err("not a PBM (P1 or P4) image".to_string());
+    };
+    let (fields, pixels) = pbm_header(data, 2)?;
+    let (width, height) = (fields[0], fields[1]);
+    let count = match width.checked_mul(height) {
+        Some(count) => count as usize,
+        None => { return Err("PBM is too large".to_string()); }
+    };
+
+    // Check the pixel data is all there before allocating the bitmap.
+    if raw {
+        let row_bytes = width.div_ceil(8) as usize;
+        if pixels.len() < row_bytes * height as usize {
+            return Err("PBM is truncated".to_string());
+        }
+        let mut bitmap = Bitmap::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let byte = pixels[y as usize * row_bytes + (x / 8) as usize];
+                bitmap.set(x, y, byte & (0x80 >> (x % 8)) != 0);
+            }
+        }
+        Ok(bitmap)
+    } else {
+        // Plain PBMs may have comments and whitespace anywhere.
+        let mut digits = Vec::new();
+        let mut in_comment = false;
+        for &c in pixels {
+            match c {
+                b'#' => { in_comment = true; }
+                b'\n' => { in_comment = false; }
+                b'0' | b'1' if !in_comment => { digits.push(c == b'1'); }
+                _ => {}
+            }
+        }
+        if digits.len() < count {
+            return Err("PBM is truncated".to_string());
+        }
+        let mut bitmap = Bitmap::new(width, height);
+        bitmap.pixels.copy_from_slice(&digits[..count]);
+        Ok(bitmap)
+    }
+}
+
+fn read_file(path: &str) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|e| format!("could not read {}: {}", path, e))
+}
+
+fn write_file<P: AsRef<Path>>(path: P, data: &[u8]) -> Result<(), String> {
+    fs::write(path.as_ref(), data).map_err(|e| format!("could not write {}: {}", path.as_ref().display(), e))
+}
+
+fn parse_number(arg: &str, what: &str) -> Result<u32, String> {
+    arg.parse().map_err(|_| format!("{} must be a number, not {:?}", what, arg))
+}
+
+fn parse_size(arg: &str) -> Result<(u32, u32), String> {
+    let mut parts = arg.splitn(2, 'x');
+    match (parts.next().map(str::parse), parts.next().map(str::parse)) {
+        (Some(Ok(width)), Some(Ok(height))) if width > 0 && height > 0 => Ok((width, height)),
+        _ => Err(format!("--size must look like 20x40, not {:?}", arg)),
+    }
+}
+
+/// Fails with a description of the problem if the atlas has a partial part.
+fn check_whole(atlas: &Atlas) -> Result<(), String> {
+    if atlas.leftover() != 0 {
+        return Err(format!("{} bytes is {} whole {}x{} parts of {} bytes and {} bytes left over",
+                           atlas.bits.len(), atlas.part_count(), atlas.part_width, atlas.part_height,
+                           atlas.part_bytes(), atlas.leftover()));
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), String> {
+    let mut args = args;
+    let mut size = (BUILTIN_PART_WIDTH, BUILTIN_PART_HEIGHT);
+    if args.first().map(String::as_str) == Some("--size") {
+        size = parse_size(args.get(1).ok_or(USAGE)?)?;
+        args = &args[2..];
+    }
+    if args.len() < 2 {
+        return Err(USAGE.to_string());
+    }
+    let (path, command, rest) = (&args[0], args[1].as_str(), &args[2..]);
+
+    let mut atlas = Atlas {
+        part_width: size.0,
+        part_height: size.1,
+        bits: if command == "build" { Vec::new() } else { read_file(path)? },
+    };
+
+    match (command, rest.len()) {
+        ("check", 0) => {
+            check_whole(&atlas)?;
+            println!("{}: {} parts of {}x{} ({} bytes each)",
+                     path, atlas.part_count(), atlas.part_width, atlas.part_height, atlas.part_bytes());
+        }
+        ("sheet", 1) | ("sheet", 2) => {
+            let columns = match rest.get(1) {
+                Some(columns) => parse_number(columns, "columns")?.max(1),
+                None => 16,
+            };
+            write_file(&rest[0], &write_pbm_raw(&contact_sheet(&atlas, columns)))?;
+        }
+        ("extract", 2) => {
+            let part = parse_number(&rest[0], "part")?;
+            if part >= atlas.part_count() {
+                return Err(format!("there are only {} parts", atlas.part_count()));
+            }
+            write_file(&rest[1], &write_pbm_plain(&atlas.part(part)))?;
+        }
+        ("replace", 2) => {
+            check_whole(&atlas)?;
+            let part = parse_number(&rest[0], "part")?;
+            let bitmap = read_pbm(&read_file(&rest[1])?)?;
+            atlas.set_part(part, &bitmap).map_err(|e| format!("{}: {}", rest[1], e))?;
+            write_file(path, &atlas.bits)?;
+        }
+        ("split", 1) => {
+            let dir = Path::new(&rest[0]);
+            fs::create_dir_all(dir).map_err(|e| format!("could not create {}: {}", dir.display(), e))?;
+            for part in 0..atlas.part_count() {
+                write_file(dir.join(format!("{:03}.pbm", part)), &write_pbm_plain(&atlas.part(part)))?;
+            }
+        }
+        ("build", n) if n > 0 => {
+            for (part, image) in rest.iter().enumerate() {
+                let bitmap = read_pbm(&read_file(image)?)?;
+                atlas.set_part(part as u32, &bitmap).map_err(|e| format!("{}: {}", image, e))?;
+            }
+            write_file(path, &atlas.bits)?;
+        }
+        _ => { return Err(USAGE.to_string()); }
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(message) = run(&args) {
+        eprintln!("{}", message);
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{contact_sheet, read_pbm, write_pbm_plain, write_pbm_raw, Atlas, Bitmap};
+
+    fn atlas() -> Atlas {
+        // Two 10x2 parts: a diagonal pair of pixels, and a full row.
+        Atlas { part_width: 10, part_height: 2, bits: vec![0x01, 0x00, 0x00, 0x02, 0xff, 0x03, 0x00, 0x00] }
+    }
+
+    #[test]
+    fn parts() {
+        let atlas = atlas();
+        assert_eq!(atlas.part_count(), 2);
+        let part = atlas.part(0);
+        assert!(part.get(0, 0) && part.get(9, 1));
+        assert_eq!(part.pixels.iter().filter(|&&ink| ink).count(), 2);
+        assert_eq!(atlas.encode(&part).unwrap(), &atlas.bits[0..4]);
+    }
+
+    #[test]
+    fn replacing() {
+        let mut atlas = atlas();
+        let part = atlas.part(1);
+        atlas.set_part(0, &part).unwrap();
+        atlas.set_part(2, &part).unwrap();
+        assert_eq!(atlas.part_count(), 3);
+        assert_eq!(atlas.part(0), atlas.part(2));
+        assert!(atlas.set_part(5, &part).is_err());
+        assert!(atlas.set_part(0, &Bitmap::new(2, 2)).is_err());
+    }
+
+    #[test]
+    fn pbm_round_trip() {
+        let part = atlas().part(0);
+        assert_eq!(read_pbm(&write_pbm_plain(&part)).unwrap(), part);
+        assert_eq!(read_pbm(&write_pbm_raw(&part)).unwrap(), part);
+        assert_eq!(read_pbm(b"P1\n# comment\n2 1\n0 1").unwrap().pixels, [false, true]);
+        assert!(read_pbm(b"P1\n2 2\n01").is_err());
+        assert_eq!(read_pbm(b"P4\n65536 65536\n"), Err("PBM is too large".to_string()));
+        assert_eq!(read_pbm(b"P1\n65535 65535\n0 1"), Err("PBM is truncated".to_string()));
+        assert_eq!(read_pbm(b"P4\n65535 65535\n\xff"), Err("PBM is truncated".to_string()));
+    }
+
+    #[test]
+    fn sheet() {
+        let sheet = contact_sheet(&atlas(), 1);
+        // Two cells stacked, each a 7-row label over the part, with borders.
+        assert_eq!((sheet.width, sheet.height), (12, 2 * 10 + 1));
+        assert!(sheet.get(1, 8) && sheet.get(10, 9));
+        // The "1" label of the second part.
+        assert!(sheet.get(3, 11) && !sheet.get(2, 11));
+    }
+
+    #[test]
+    fn narrow_sheet() {
+        // Twelve 4x1 parts, two to a row, so "10" and "11" are too wide.
+        let narrow = Atlas { part_width: 4, part_height: 1, bits: vec![0; 12] };
+        let sheet = contact_sheet(&narrow, 2);
+        assert_eq!(sheet.width, 11);
+        for y in 0..sheet.height {
+            assert!(sheet.get(0, y) && sheet.get(5, y) && sheet.get(10, y), "border broken at row {}", y);
+        }
+    }
+}
@@ -0,0 +1,122 @@
+/// The color of a glyph's foreground or background.
+///
+/// Every backend shows the same `Color` the same way: RGB values are
+/// converted to whatever the display needs, and indexed colors are looked
+/// up in the palette the backend was given.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Color {
+    /// 0x00RRGGBB. The top byte is ignored.
+    Rgb(u32),
+    /// An entry of the current `Palette`.
+    Indexed(u8),
+}
+
+impl Color {
+    pub const BLACK: Color = Color::Rgb(0x000000);
+    pub const WHITE: Color = Color::Rgb(0xffffff);
+
+    pub fn rgb(red: u8, green: u8, blue: u8) -> Color {
+        Color::Rgb(((red as u32) << 16) | ((green as u32) << 8) | blue as u32)
+    }
+}
+
+/// The 16 standard terminal colors, in ANSI order: black, red, green,
+/// yellow, blue, magenta, cyan, white, then their bright versions.
+const ANSI_COLORS: [u32; 16] = [
+    0x000000, 0xaa0000, 0x00aa00, 0xaa5500, 0x0000aa, 0xaa00aa, 0x00aaaa, 0xaaaaaa,
+    0x555555, 0xff5555, 0x55ff55, 0xffff55, 0x5555ff, 0xff55ff, 0x55ffff, 0xffffff,
+];
+
+/// The colors `Color::Indexed` refers to, each 0x00RRGGBB. It can be
+/// swapped while a UI is open with `GridUiInterface::set_palette`, which
+/// recolors every indexed glyph on the screen.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Palette {
+    colors: Vec<u32>,
+}
+
+impl Palette {
+    /// Panics if there are more than 256 colors.
+    pub fn new(colors: Vec<u32>) -> Palette {
+        assert!(colors.len() <= 256, "a palette has at most 256 colors");
+        Palette { colors: colors }
+    }
+
+    /// The 16 standard terminal colors.
+    pub fn ansi() -> Palette {
+        Palette::new(ANSI_COLORS.to_vec())
+    }
+
+    /// The 256 colors of xterm: the 16 standard ones, a 6x6x6 color cube
+    /// and 24 grays.
+    pub fn xterm() -> Palette {
+        let mut colors = ANSI_COLORS.to_vec();
+        let level = |n: u32| if n == 0 { 0 } else { 55 + 40 * n };
+        for red in 0..6 {
+            for green in 0..6 {
+                for blue in 0..6 {
+                    colors.push((level(red) << 16) | (level(green) << 8) | level(blue));
+                }
+            }
+        }
+        for n in 0..24 {
+            let gray = 8 + 10 * n;
+            colors.push((gray << 16) | (gray << 8) | gray);
+        }
+        Palette::new(colors)
+    }
+
+    pub fn colors(&self) -> &[u32] {
+        &self.colors[..]
+    }
+
+    /// Changes one entry. Panics if `index` is past the end.
+    pub fn set(&mut self, index: u8, rgb: u32) {
+        self.colors[index as usize] = rgb & 0xffffff;
+    }
+
+    /// `color` as 0x00RRGGBB. Indexes past the end of the palette are black.
+    pub fn rgb(&self, color: Color) -> u32 {
+        match color {
+            Color::Rgb(rgb) => rgb & 0xffffff,
+            Color::Indexed(index) => self.colors.get(index as usize).cloned().unwrap_or(0),
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::xterm()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Color, Palette};
+
+    #[test]
+    fn lookup() {
+        let palette = Palette::xterm();
+        assert_eq!(palette.colors().len(), 256);
+        assert_eq!(palette.rgb(Color::Indexed(9)), 0xff5555);
+        assert_eq!(palette.rgb(Color::Indexed(16)), 0x000000);
+        assert_eq!(palette.rgb(Color::Indexed(21)), 0x0000ff);
+        assert_eq!(palette.rgb(Color::Indexed(196)), 0xff0000);
+        assert_eq!(palette.rgb(Color::Indexed(231)), 0xffffff);
+        assert_eq!(palette.rgb(Color::Indexed(232)), 0x080808);
+        assert_eq!(palette.rgb(Color::Indexed(255)), 0xeeeeee);
+
+        let mut ansi = Palette::ansi();
+        assert_eq!(ansi.rgb(Color::Indexed(16)), 0x000000);
+        ansi.set(1, 0xff123456);
+        assert_eq!(ansi.rgb(Color::Indexed(1)), 0x123456);
+    }
+
+    #[test]
+    fn rgb() {
+        let palette = Palette::default();
+        assert_eq!(Color::rgb(0x12, 0x34, 0x56), Color::Rgb(0x123456));
+        assert_eq!(palette.rgb(Color::Rgb(0xff123456)), 0x123456);
+        assert_eq!(palette.rgb(Color::WHITE), 0xffffff);
+    }
+}
@@ -0,0 +1,214 @@
+use std::time::Duration;
+
+use atlas::{self, GlyphAtlas};
+use color::Palette;
+
+/// Widest or tallest a grid is drawn, in pixels, as X11 coordinates are
+/// 16-bit. Grid sizes are kept within it.
+pub const MAX_GRID_PIXELS: u32 = i16::MAX as u32;
+
+/// How glyphs get to the screen.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Rendering {
+    /// Glyph parts are sent to the X server once and drawn there. The least
+    /// traffic, especially over a network.
+    Server,
+    /// The screen is rasterized in the client, exactly as the headless
+    /// backend does, and sent as an image, through shared memory when the
+    /// X server is local. The Windows backend always draws this way.
+    Client,
+}
+
+/// Settings for opening a grid UI. Built up from `GridUiConfig::new()`:
+///
+/// ```
+/// use gridui::config::GridUiConfig;
+///
+/// let config = GridUiConfig::new()
+///     .title("Inventory")
+///     .grid_size(100, 30)
+///     .min_grid_size(40, 10);
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GridUiConfig {
+    pub title: String,
+    /// Initial (columns, rows).
+    pub grid_size: (u32, u32),
+    /// Whole-number factor cells are enlarged by. Cells are 20x40 pixels at
+    /// scale 1 with the built-in glyphs.
+    pub scale: u32,
+    /// Glyphs to draw with instead of the built-in ones, e.g. from
+    /// `font::load`. Cells are the size of its parts.
+    pub font: Option<GlyphAtlas>,
+    pub rendering: Rendering,
+    /// The colors `Color::Indexed` starts out referring to.
+    pub palette: Palette,
+    /// How long `Attributes::BLINK` glyphs stay shown, then hidden.
+    pub blink_interval: Duration,
+    pub resizable: bool,
+    pub min_grid_size: Option<(u32, u32)>,
+    pub max_grid_size: Option<(u32, u32)>,
+}
+
+impl GridUiConfig {
+    pub fn new() -> GridUiConfig {
+        GridUiConfig {
+            title: "Grid UI".to_string(),
+            grid_size: (32, 12),
+            scale: 1,
+            resizable: true,
+            min_grid_size: None,
+            max_grid_size: None,
+            font: None,
+            rendering: Rendering::Server,
+            palette: Palette::default(),
+            blink_interval: Duration::from_millis(500),
+        }
+    }
+
+    pub fn title(mut self, title: &str) -> GridUiConfig {
+        self.title = title.to_string();
+        self
+    }
+
+    pub fn grid_size(mut self, cols: u32, rows: u32) -> GridUiConfig {
+        self.grid_size = (cols, rows);
+        self
+    }
+
+    /// Panics if `scale` is 0.
+    pub fn scale(mut self, scale: u32) -> GridUiConfig {
+        assert!(scale > 0, "scale must be at least 1");
+        self.scale = scale;
+        self
+    }
+
+    pub fn font(mut self, font: GlyphAtlas) -> GridUiConfig {
+        self.font = Some(font);
+        self
+    }
+
+    pub fn rendering(mut self, rendering: Rendering) -> GridUiConfig {
+        self.rendering = rendering;
+        self
+    }
+
+    pub fn palette(mut self, palette: Palette) -> GridUiConfig {
+        self.palette = palette;
+        self
+    }
+
+    /// Panics if `interval` is zero.
+    pub fn blink_interval(mut self, interval: Duration) -> GridUiConfig {
+        assert!(interval > Duration::from_millis(0), "blink interval must not be zero");
+        self.blink_interval = interval;
+        self
+    }
+
+    pub fn resizable(mut self, resizable: bool) -> GridUiConfig {
+        self.resizable = resizable;
+        self
+    }
+
+    pub fn min_grid_size(mut self, cols: u32, rows: u32) -> GridUiConfig {
+        self.min_grid_size = Some((cols, rows));
+        self
+    }
+
+    pub fn max_grid_size(mut self, cols: u32, rows: u32) -> GridUiConfig {
+        self.max_grid_size = Some((cols, rows));
+        self
+    }
+
+    /// The smallest grid the user may resize to. This is the initial size if
+    /// the window isn't resizable.
+    pub fn effective_min_grid_size(&self) -> (u32, u32) {
+        let min = if self.resizable { self.min_grid_size.unwrap_or((1, 1)) } else { self.grid_size };
+        let largest = self.largest_grid_size();
+        (min.0.min(largest.0), min.1.min(largest.1))
+    }
+
+    /// The glyphs to draw with, at the configured scale.
+    pub fn atlas(&self) -> GlyphAtlas {
+        match self.font {
+            Some(ref font) => font.scaled(self.scale),
+            None => GlyphAtlas::builtin().scaled(self.scale),
+        }
+    }
+
+    /// Width and height of a cell in pixels, the size of the atlas's parts.
+    pub fn cell_size(&self) -> (u32, u32) {
+        let (width, height) = match self.font {
+            Some(ref font) => (font.part_width(), font.part_height()),
+            None => (atlas::BUILTIN_PART_WIDTH, atlas::BUILTIN_PART_HEIGHT),
+        };
+        (width.saturating_mul(self.scale), height.saturating_mul(self.scale))
+    }
+
+    /// The most cells that fit in `MAX_GRID_PIXELS` each way, and at least
+    /// one.
+    pub fn largest_grid_size(&self) -> (u32, u32) {
+        let (width, height) = self.cell_size();
+        ((MAX_GRID_PIXELS / width).max(1), (MAX_GRID_PIXELS / height).max(1))
+    }
+
+    /// The largest grid the user may resize to. This is the initial size if
+    /// the window isn't resizable, and never more than `largest_grid_size`.
+    pub fn effective_max_grid_size(&self) -> (u32, u32) {
+        let max = if self.resizable { self.max_grid_size } else { Some(self.grid_size) };
+        let largest = self.largest_grid_size();
+        match max {
+            Some(max) => (max.0.min(largest.0), max.1.min(largest.1)),
+            None => largest,
+        }
+    }
+
+    /// Limits `size` to the allowed range.
+    pub fn clamp_grid_size(&self, size: (u32, u32)) -> (u32, u32) {
+        let (min, max) = (self.effective_min_grid_size(), self.effective_max_grid_size());
+        (size.0.max(min.0).min(max.0), size.1.max(min.1).min(max.1))
+    }
+}
+
+impl Default for GridUiConfig {
+    fn default() -> GridUiConfig {
+        GridUiConfig::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GridUiConfig;
+
+    #[test]
+    fn clamping() {
+        let config = GridUiConfig::new().min_grid_size(10, 5).max_grid_size(100, 50);
+        assert_eq!(config.clamp_grid_size((1, 60)), (10, 50));
+        assert_eq!(config.clamp_grid_size((20, 20)), (20, 20));
+
+        let fixed = GridUiConfig::new().grid_size(40, 20).resizable(false);
+        assert_eq!(fixed.clamp_grid_size((80, 10)), (40, 20));
+    }
+
+    #[test]
+    fn pixel_limit() {
+        // 20x40 cells at scale 2 are 40x80 pixels.
+        let config = GridUiConfig::new().scale(2);
+        assert_eq!(config.cell_size(), (40, 80));
+        assert_eq!(config.largest_grid_size(), (819, 409));
+        assert_eq!(config.clamp_grid_size((1000, 1000)), (819, 409));
+        assert_eq!(config.effective_max_grid_size(), (819, 409));
+
+        let fixed = GridUiConfig::new().scale(2).grid_size(2000, 2000).resizable(false);
+        assert_eq!(fixed.effective_min_grid_size(), (819, 409));
+        assert_eq!(fixed.clamp_grid_size((2000, 2000)), (819, 409));
+    }
+
+    #[test]
+    fn unlimited() {
+        // Only the pixel limit applies.
+        let config = GridUiConfig::new();
+        assert_eq!(config.effective_max_grid_size(), (1638, 819));
+        assert_eq!(config.clamp_grid_size((0, 800)), (1, 800));
+    }
+}
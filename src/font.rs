@@ -0,0 +1,553 @@
+//! Glyph atlases from BDF and PC Screen Font (PSF1 and PSF2) files.
+//!
+//! Every glyph in the font becomes one part, in file order, followed by a
+//! frame that unknown glyph codes are drawn as. Glyph codes are mapped to the
+//! glyph for their character, so a font's own symbols can be reached by
+//! adding codes to the atlas's table with the glyph's index as the part.
+//!
+//! Fonts can be read at runtime with `load`, or built in with
+//! `from_bytes(include_bytes!("font.psf"))`.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::str;
+
+use atlas::GlyphAtlas;
+use glyph_parts::{MissingCharacter, PartTable};
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF1_MODE_HAS_TABLE: u8 = 0x06;
+const PSF1_SEPARATOR: u16 = 0xffff;
+const PSF1_START_SEQUENCE: u16 = 0xfffe;
+
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xb5, 0x4a, 0x86];
+const PSF2_HAS_TABLE: u32 = 0x01;
+const PSF2_SEPARATOR: u8 = 0xff;
+const PSF2_START_SEQUENCE: u8 = 0xfe;
+
+/// Glyphs wider or taller than this are taken to be a corrupt header.
+const MAX_GLYPH_SIZE: u32 = 512;
+/// The most memory an atlas's parts may take, so that a small file can't
+/// ask for a huge one.
+const MAX_ATLAS_BYTES: usize = 64 << 20;
+
+/// Why a font couldn't be loaded.
+#[derive(Debug)]
+pub enum FontError {
+    Io(io::Error),
+    /// The data is neither BDF nor an uncompressed PSF.
+    Unrecognized,
+    Malformed(String),
+}
+
+impl fmt::Display for FontError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FontError::Io(ref err) => write!(f, "could not read font: {}", err),
+            FontError::Unrecognized => write!(f, "not a BDF or PSF font"),
+            FontError::Malformed(ref msg) => write!(f, "malformed font: {}", msg),
+        }
+    }
+}
+
+impl Error for FontError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            FontError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FontError {
+    fn from(err: io::Error) -> FontError {
+        FontError::Io(err)
+    }
+}
+
+fn malformed(msg: &str) -> FontError {
+    FontError::Malformed(msg.to_string())
+}
+
+/// Collects glyph bitmaps into atlas parts.
+struct AtlasBuilder {
+    width: u32,
+    height: u32,
+    bits: Vec<u8>,
+    parts: u32,
+    chars: HashMap<char, u32>,
+}
+
+impl AtlasBuilder {
+    fn new(width: u32, height: u32) -> Result<AtlasBuilder, FontError> {
+        if width == 0 || height == 0 {
+            return Err(malformed("glyphs have no area"));
+        }
+        if width > MAX_GLYPH_SIZE || height > MAX_GLYPH_SIZE {
+            return Err(malformed("glyphs are too large"));
+        }
+        Ok(AtlasBuilder {
+            width: width,
+            height: height,
+            bits: Vec::new(),
+            parts: 0,
+            chars: HashMap::new(),
+        })
+    }
+
+    fn row_bytes(&self) -> usize {
+        self.width.div_ceil(8) as usize
+    }
+
+    fn part_bytes(&self) -> usize {
+        self.row_bytes() * self.height as usize
+    }
+
+    /// Adds an empty part and returns its number.
+    fn add_part(&mut self) -> Result<u32, FontError> {
+        // Leave room for the frame `finish` adds.
+        if self.bits.len() + 2 * self.part_bytes() > MAX_ATLAS_BYTES {
+            return Err(malformed("too many glyphs"));
+        }
+        Ok(self.add_frame_part())
+    }
+
+    fn add_frame_part(&mut self) -> u32 {
+        let end = self.bits.len() + self.part_bytes();
+        self.bits.resize(end, 0);
+        self.parts += 1;
+        self.parts - 1
+    }
+
+    /// Sets a pixel of `part`, ignoring those outside it.
+    fn set(&mut self, part: u32, x: i64, y: i64) {
+        if x < 0 || y < 0 || x >= self.width as i64 || y >= self.height as i64 {
+            return;
+        }
+        let row_start = (part as usize * self.height as usize + y as usize) * self.row_bytes();
+        self.bits[row_start + (x / 8) as usize] |= 1 << (x % 8);
+    }
+
+    /// Draws `ch` with `part`, unless an earlier glyph already does.
+    fn map(&mut self, ch: char, part: u32) {
+        self.chars.entry(ch).or_insert(part);
+    }
+
+    /// Adds a part from MSB-first rows of `row_bytes` bytes each, the layout
+    /// both BDF and PSF use.
+    fn add_msb_first(&mut self, data: &[u8], row_bytes: usize) -> Result<u32, FontError> {
+        let part = self.add_part()?;
+        for (y, row) in data.chunks(row_bytes).enumerate() {
+            for x in 0..row.len() * 8 {
+                if row[x / 8] & (0x80 >> (x % 8)) != 0 {
+                    self.set(part, x as i64, y as i64);
+                }
+            }
+        }
+        Ok(part)
+    }
+
+    fn finish(mut self) -> GlyphAtlas {
+        let frame = self.add_frame_part();
+        let (right, bottom) = (self.width as i64 - 2, self.height as i64 - 2);
+        for x in 1..=right {
+            self.set(frame, x, 1);
+            self.set(frame, x, bottom);
+        }
+        for y in 1..=bottom {
+            self.set(frame, 1, y);
+            self.set(frame, right, y);
+        }
+
+        let chars = self.chars;
+        let table = PartTable::from_chars(MissingCharacter::Frame(frame), |ch| chars.get(&ch).cloned());
+        GlyphAtlas::new(self.width, self.height, self.bits, table)
+    }
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, FontError> {
+    data.get(offset..offset + 4)
+        .map(|b| b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24)
+        .ok_or_else(|| malformed("truncated PSF header"))
+}
+
+/// Reads a PSF1 font.
+pub fn from_psf1(data: &[u8]) -> Result<GlyphAtlas, FontError> {
+    if !data.starts_with(&PSF1_MAGIC) {
+        return Err(FontError::Unrecognized);
+    }
+    if data.len() < 4 {
+        return Err(malformed("truncated PSF header"));
+    }
+    let mode = data[2];
+    let char_size = data[3] as usize;
+    let count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+
+    let glyphs_end = 4 + count * char_size;
+    let glyphs = data.get(4..glyphs_end).ok_or_else(|| malformed("truncated glyphs"))?;
+    let mut builder = AtlasBuilder::new(8, char_size as u32)?;
+    for glyph in glyphs.chunks(char_size) {
+        builder.add_msb_first(glyph, 1)?;
+    }
+
+    if mode & PSF1_MODE_HAS_TABLE == 0 {
+        map_by_index(&mut builder, count);
+        return Ok(builder.finish());
+    }
+
+    let mut part = 0;
+    let mut in_sequence = false;
+    for entry in data[glyphs_end..].chunks(2) {
+        if part >= count as u32 {
+            break;
+        }
+        let entry = match *entry {
+            [low, high] => low as u16 | (high as u16) << 8,
+            _ => { return Err(malformed("truncated Unicode table")); }
+        };
+        match entry {
+            PSF1_SEPARATOR => { part += 1; in_sequence = false; }
+            PSF1_START_SEQUENCE => { in_sequence = true; }
+            // Sequences are several characters drawn as one; there's no glyph
+            // code for those.
+            _ if in_sequence => {}
+            _ => {
+                if let Some(ch) = ::std::char::from_u32(entry as u32) {
+                    builder.map(ch, part);
+                }
+            }
+        }
+    }
+    Ok(builder.finish())
+}
+
+/// Reads a PSF2 font.
+pub fn from_psf2(data: &[u8]) -> Result<GlyphAtlas, FontError> {
+    if !data.starts_with(&PSF2_MAGIC) {
+        return Err(FontError::Unrecognized);
+    }
+    let header_size = u32_at(data, 8)? as usize;
+    let flags = u32_at(data, 12)?;
+    let count = u32_at(data, 16)? as usize;
+    let char_size = u32_at(data, 20)? as usize;
+    let height = u32_at(data, 24)?;
+    let width = u32_at(data, 28)?;
+
+    let row_bytes = width.div_ceil(8) as usize;
+    if Some(char_size) != row_bytes.checked_mul(height as usize) {
+        return Err(malformed("glyph size does not match its dimensions"));
+    }
+    let glyphs_end = count.checked_mul(char_size)
+        .and_then(|size| size.checked_add(header_size))
+        .ok_or_else(|| malformed("too many glyphs"))?;
+    let glyphs = data.get(header_size..glyphs_end).ok_or_else(|| malformed("truncated glyphs"))?;
+    let mut builder = AtlasBuilder::new(width, height)?;
+    for glyph in glyphs.chunks(char_size) {
+        builder.add_msb_first(glyph, row_bytes)?;
+    }
+
+    if flags & PSF2_HAS_TABLE == 0 {
+        map_by_index(&mut builder, count);
+        return Ok(builder.finish());
+    }
+
+    let table = &data[glyphs_end..];
+    for (part, entries) in table.split(|&b| b == PSF2_SEPARATOR).take(count).enumerate() {
+        // Anything after the first sequence marker is a sequence.
+        let singles = entries.split(|&b| b == PSF2_START_SEQUENCE).next().unwrap_or(&[]);
+        let singles = str::from_utf8(singles).map_err(|_| malformed("invalid UTF-8 in Unicode table"))?;
+        for ch in singles.chars() {
+            builder.map(ch, part as u32);
+        }
+    }
+    Ok(builder.finish())
+}
+
+/// Fonts without a Unicode table are taken to be indexed by code point.
+fn map_by_index(builder: &mut AtlasBuilder, count: usize) {
+    for part in 0..count as u32 {
+        if let Some(ch) = ::std::char::from_u32(part) {
+            builder.map(ch, part);
+        }
+    }
+}
+
+/// The keyword a BDF line starts with.
+fn bdf_keyword(line: &str) -> &str {
+    line.split_whitespace().next().unwrap_or("")
+}
+
+/// The numbers following `keyword` on a BDF line.
+fn bdf_numbers(line: &str, keyword: &str, line_number: usize) -> Result<Vec<i64>, FontError> {
+    line[keyword.len()..].split_whitespace()
+        .map(|field| field.parse())
+        .collect::<Result<Vec<i64>, _>>()
+        .map_err(|_| FontError::Malformed(format!("line {}: bad {}", line_number, keyword)))
+}
+
+/// Reads a BDF font. Cells are the size of its FONTBOUNDINGBOX, and
+/// encodings are taken to be Unicode code points.
+pub fn from_bdf(data: &[u8]) -> Result<GlyphAtlas, FontError> {
+    if !data.starts_with(b"STARTFONT") {
+        return Err(FontError::Unrecognized);
+    }
+    // Properties like COPYRIGHT are often Latin-1, which any byte decodes
+    // as. Only the keywords and hex digits matter, and those are ASCII.
+    let text: String = data.iter().map(|&byte| byte as char).collect();
+
+    let mut builder: Option<AtlasBuilder> = None;
+    // Left edge and top of the cell relative to the glyph origin, y up.
+    let mut cell_origin = (0, 0);
+    let mut encoding = None;
+    let mut bbx = None;
+    let mut bitmap: Option<(u32, i64)> = None;
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+
+        if let Some((part, ref mut row)) = bitmap {
+            if line == "ENDCHAR" {
+                bitmap = None;
+                continue;
+            }
+            let (width, height, x_offset, y_offset) = bbx.unwrap();
+            let builder = builder.as_mut().unwrap();
+            let top = cell_origin.1 - (y_offset + height);
+            for (byte_index, hex) in line.as_bytes().chunks(2).enumerate() {
+                let byte = str::from_utf8(hex).ok().and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                    .ok_or_else(|| FontError::Malformed(format!("line {}: bad bitmap row", line_number)))?;
+                for bit in 0..8 {
+                    let x = byte_index as i64 * 8 + bit;
+                    if x < width && byte & (0x80 >> bit) != 0 {
+                        builder.set(part, x + x_offset - cell_origin.0, top + *row);
+                    }
+                }
+            }
+            *row += 1;
+            continue;
+        }
+
+        match bdf_keyword(line) {
+            "FONTBOUNDINGBOX" => {
+                match bdf_numbers(line, "FONTBOUNDINGBOX", line_number)?[..] {
+                    [width, height, x_offset, y_offset] if width > 0 && height > 0 => {
+                        // Saturated, so that huge boxes are rejected rather than wrapped.
+                        let size = |n: i64| n.min(u32::MAX as i64) as u32;
+                        builder = Some(AtlasBuilder::new(size(width), size(height))?);
+                        cell_origin = (x_offset, height + y_offset);
+                    }
+                    _ => { return Err(FontError::Malformed(format!("line {}: bad FONTBOUNDINGBOX", line_number))); }
+                }
+            }
+            "STARTCHAR" => {
+                encoding = None;
+                bbx = None;
+            }
+            "ENCODING" => {
+                // -1 means unencoded. An optional second number is a
+                // non-standard encoding, which we can't use.
+                encoding = bdf_numbers(line, "ENCODING", line_number)?.first().cloned()
+                    .filter(|&code| code >= 0)
+                    .and_then(|code| ::std::char::from_u32(code as u32));
+            }
+            "BBX" => {
+                match bdf_numbers(line, "BBX", line_number)?[..] {
+                    [width, height, x_offset, y_offset] => { bbx = Some((width, height, x_offset, y_offset)); }
+                    _ => { return Err(FontError::Malformed(format!("line {}: bad BBX", line_number))); }
+                }
+            }
+            "BITMAP" => {
+                let builder = builder.as_mut().ok_or_else(|| malformed("glyph before FONTBOUNDINGBOX"))?;
+                if bbx.is_none() {
+                    return Err(FontError::Malformed(format!("line {}: glyph without BBX", line_number)));
+                }
+                let part = builder.add_part()?;
+                if let Some(ch) = encoding {
+                    builder.map(ch, part);
+                }
+                bitmap = Some((part, 0));
+            }
+            _ => {}
+        }
+    }
+
+    if bitmap.is_some() {
+        return Err(malformed("missing ENDCHAR"));
+    }
+    builder.map(|builder| builder.finish()).ok_or_else(|| malformed("no FONTBOUNDINGBOX"))
+}
+
+/// Reads a font in any of the supported formats.
+pub fn from_bytes(data: &[u8]) -> Result<GlyphAtlas, FontError> {
+    if data.starts_with(&PSF1_MAGIC) {
+        return from_psf1(data);
+    }
+    if data.starts_with(&PSF2_MAGIC) {
+        return from_psf2(data);
+    }
+    from_bdf(data)
+}
+
+/// Reads a font file in any of the supported formats.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<GlyphAtlas, FontError> {
+    from_bytes(&fs::read(path)?)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{from_bdf, from_bytes, from_psf1, from_psf2, FontError};
+    use glyphcode::from_char;
+
+    const BDF: &str = "STARTFONT 2.1
+FONT -test-fixed-medium-r-normal--4-40-75-75-c-40-iso10646-1
+SIZE 4 75 75
+FONTBOUNDINGBOX 4 6 0 -1
+CHARS 2
+STARTCHAR A
+ENCODING 65
+SWIDTH 500 0
+DWIDTH 4 0
+BBX 3 4 0 0
+BITMAP
+40
+A0
+E0
+A0
+ENDCHAR
+STARTCHAR logo
+ENCODING -1
+BBX 2 2 1 -1
+BITMAP
+C0
+C0
+ENDCHAR
+ENDFONT
+";
+
+    fn rows(atlas: &::atlas::GlyphAtlas, part: u32) -> Vec<String> {
+        (0..atlas.part_height()).map(|y| {
+            (0..atlas.part_width()).map(|x| if atlas.is_set(part, x, y) { '#' } else { '.' }).collect()
+        }).collect()
+    }
+
+    #[test]
+    fn bdf() {
+        let atlas = from_bdf(BDF.as_bytes()).unwrap();
+        assert_eq!((atlas.part_width(), atlas.part_height(), atlas.part_count()), (4, 6, 3));
+        assert_eq!(rows(&atlas, 0), ["....", ".#..", "#.#.", "###.", "#.#.", "...."]);
+        // Unencoded, and below the baseline.
+        assert_eq!(rows(&atlas, 1), ["....", "....", "....", "....", ".##.", ".##."]);
+
+        assert_eq!(atlas.parts(from_char('A').unwrap()), vec![0]);
+        // Everything else is the frame after the glyphs.
+        assert_eq!(atlas.parts(from_char('B').unwrap()), vec![2]);
+        assert_eq!(rows(&atlas, 2), ["....", ".##.", ".##.", ".##.", ".##.", "...."]);
+
+        // A Latin-1 copyright sign, which isn't valid UTF-8.
+        let (head, tail) = BDF.split_at(BDF.find("CHARS").unwrap());
+        let latin1 = [head.as_bytes(), b"COPYRIGHT \"\xa9 1990\"\n", tail.as_bytes()].concat();
+        assert_eq!(from_bdf(&latin1).unwrap().part_count(), 3);
+    }
+
+    #[test]
+    fn bdf_errors() {
+        let truncated = &BDF[..BDF.find("ENDCHAR").unwrap()];
+        match from_bdf(truncated.as_bytes()) {
+            Err(FontError::Malformed(_)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        match from_bdf(&BDF.replace("BBX 3 4 0 0", "BBX 3 x 0 0").into_bytes()) {
+            Err(FontError::Malformed(msg)) => assert_eq!(msg, "line 10: bad BBX"),
+            other => panic!("unexpected {:?}", other),
+        }
+        match from_bytes(b"hello") {
+            Err(FontError::Unrecognized) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        match from_bdf(b"\x7fELF\x02\x01\x01\xff") {
+            Err(FontError::Unrecognized) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+        match from_bdf(BDF.replace("FONTBOUNDINGBOX 4 6", "FONTBOUNDINGBOX 4 4294967302").as_bytes()) {
+            Err(FontError::Malformed(msg)) => assert_eq!(msg, "glyphs are too large"),
+            other => panic!("unexpected {:?}", other),
+        }
+        // Each BITMAP line is a whole part, so a short file can ask for many.
+        let many = format!("STARTFONT 2.1\nFONTBOUNDINGBOX 512 512 0 0\nBBX 1 1 0 0\n{}", "BITMAP\nENDCHAR\n".repeat(2100));
+        match from_bdf(many.as_bytes()) {
+            Err(FontError::Malformed(msg)) => assert_eq!(msg, "too many glyphs"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bdf_keywords() {
+        // Only whole keywords count.
+        let atlas = from_bdf(BDF.replace("ENCODING 65", "ENCODINGS 65").as_bytes()).unwrap();
+        assert_eq!(atlas.parts(from_char('A').unwrap()), vec![2]);
+    }
+
+    #[test]
+    fn psf1() {
+        // 256 glyphs of 2 rows; glyph 1 is a bar.
+        let mut data = vec![0x36, 0x04, 0x02, 2];
+        let mut glyphs = vec![0u8; 256 * 2];
+        glyphs[2] = 0xf0;
+        data.extend(glyphs);
+        // Glyph 0 has no characters, glyph 1 is 'e' and 'é' and a sequence.
+        data.extend(&[0xff, 0xff]);
+        data.extend(&[b'e', 0, 0xe9, 0, 0xfe, 0xff, b'e', 0, 0x01, 0x03, 0xff, 0xff]);
+        for _ in 2..256 {
+            data.extend(&[0xff, 0xff]);
+        }
+
+        let atlas = from_psf1(&data).unwrap();
+        assert_eq!((atlas.part_width(), atlas.part_height(), atlas.part_count()), (8, 2, 257));
+        assert_eq!(rows(&atlas, 1), ["####....", "........"]);
+        assert_eq!(atlas.parts(from_char('e').unwrap()), vec![1]);
+        assert_eq!(atlas.parts(from_char('\u{e9}').unwrap()), vec![1]);
+        assert_eq!(atlas.parts(from_char('a').unwrap()), vec![256]);
+    }
+
+    #[test]
+    fn psf2() {
+        let mut data = vec![0x72, 0xb5, 0x4a, 0x86];
+        // Version, header size, flags, count, glyph size, height, width.
+        for &field in &[0u32, 32, 1, 2, 6, 3, 10] {
+            data.extend(&[field as u8, (field >> 8) as u8, 0, 0]);
+        }
+        data.extend(&[0x80, 0x00, 0x00, 0x40, 0, 0]);
+        data.extend(&[0xff, 0xff, 0xc0, 0xc0, 0xff, 0xff]);
+        data.extend("?".as_bytes());
+        data.push(0xff);
+        data.extend("\u{160}S".as_bytes());
+        data.push(0xff);
+
+        let atlas = from_bytes(&data).unwrap();
+        assert_eq!((atlas.part_width(), atlas.part_height(), atlas.part_count()), (10, 3, 3));
+        assert_eq!(rows(&atlas, 0), ["#.........", ".........#", ".........."]);
+        assert_eq!(rows(&atlas, 1), ["##########", "##......##", "##########"]);
+        assert_eq!(atlas.parts(from_char('?').unwrap()), vec![0]);
+        assert_eq!(atlas.parts(from_char('\u{160}').unwrap()), vec![1]);
+        assert_eq!(atlas.parts(from_char('S').unwrap()), vec![1]);
+
+        data.truncate(40);
+        assert!(from_psf2(&data).is_err());
+
+        // No glyphs, but 65535x65535 of them.
+        let mut huge = vec![0x72, 0xb5, 0x4a, 0x86];
+        for &field in &[0u32, 32, 0, 0, 8192 * 65535, 65535, 65535] {
+            huge.extend(&field.to_le_bytes());
+        }
+        match from_psf2(&huge) {
+            Err(FontError::Malformed(msg)) => assert_eq!(msg, "glyphs are too large"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
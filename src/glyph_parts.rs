@@ -1,37 +1,275 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
 
+use glyphcode::{self, GlyphCode, ACCENT_MASK, CEDILLA, RING};
+
+const MISSING_CHARACTER_FRAME: u32 = 0;
+// One hex digit at each of the 8 positions inside the frame, two to a row,
+// most significant first: part start + position*16 + digit.
 const MISSING_CHARACTER_PIECES_START: u32 = 1;
 const MISSING_CHARACTER_PIECES_COUNT: u32 = 16*8;
 const SYMBOLS_1: u32 = MISSING_CHARACTER_PIECES_START + MISSING_CHARACTER_PIECES_COUNT;
-const DIGITS_START: u32 = SYMBOLS_1 + 10; 
+const DIGITS_START: u32 = SYMBOLS_1 + 10;
+const UPPERCASE_START: u32 = DIGITS_START + 10;
+const LOWERCASE_START: u32 = UPPERCASE_START + 26;
+const DOTLESS_I: u32 = LOWERCASE_START + 26;
+const DOTLESS_J: u32 = DOTLESS_I + 1;
+// Acute, grave, tilde, diaeresis, circumflex, caron, macron, positioned over
+// lowercase and then uppercase letters.
+const LOWERCASE_ACCENTS_START: u32 = DOTLESS_J + 1;
+const UPPERCASE_ACCENTS_START: u32 = LOWERCASE_ACCENTS_START + 7;
+const SYMBOLS_2: u32 = UPPERCASE_ACCENTS_START + 7;
+const SYMBOLS_3: u32 = SYMBOLS_2 + 9;
+const LOWERCASE_RING: u32 = SYMBOLS_3 + 14;
+const UPPERCASE_RING: u32 = LOWERCASE_RING + 1;
+const CEDILLA_PART: u32 = UPPERCASE_RING + 1;
+
+/// Characters with a part of their own in the built-in atlas, each run
+/// starting at the given part.
+const BUILTIN_RUNS: [(&str, u32); 6] = [
+    (" _-.,/\\:;@", SYMBOLS_1),
+    ("0123456789", DIGITS_START),
+    ("ABCDEFGHIJKLMNOPQRSTUVWXYZ", UPPERCASE_START),
+    ("abcdefghijklmnopqrstuvwxyz", LOWERCASE_START),
+    ("!#$%^&*=+", SYMBOLS_2),
+    ("?()[]{}<>\"'`|~", SYMBOLS_3),
+];
+
+/// Lowercase letters tall enough that accents must sit at capital height.
+const ASCENDERS: &str = "bdfhklt";
+
+/// The part for `accent` drawn over a letter, using the higher marks when
+/// `tall` is set.
+fn accent_part(accent: u32, tall: bool) -> Option<u32> {
+    if accent == CEDILLA {
+        return Some(CEDILLA_PART);
+    }
+    if accent == RING {
+        return Some(if tall { UPPERCASE_RING } else { LOWERCASE_RING });
+    }
+
+    // Where each accent's part is among the seven of its size in the atlas.
+    let offset = match accent {
+        glyphcode::ACUTE => 0,
+        glyphcode::GRAVE => 1,
+        glyphcode::TILDE => 2,
+        glyphcode::DIAERESIS => 3,
+        glyphcode::CIRCUMFLEX => 4,
+        glyphcode::CARON => 5,
+        glyphcode::MACRON => 6,
+        _ => { return None; }
+    };
+    let start = if tall { UPPERCASE_ACCENTS_START } else { LOWERCASE_ACCENTS_START };
+    return Some(start + offset);
+}
+
+/// Built-in parts for an accented letter glyph code: the base letter, then
+/// its accent.
+fn accented_letter_parts(glyph: GlyphCode) -> Option<Vec<u32>> {
+    let index = (glyph & 0x0ff0) >> 4;
+    let uppercase = glyph & 0x2000 != 0;
+    let accent = glyph & ACCENT_MASK;
+
+    let letter = (b'a' + index as u8) as char;
+    let tall = uppercase || ASCENDERS.contains(letter);
+    let mark = accent_part(accent, tall)?;
+    let base = match (uppercase, letter) {
+        (false, 'i') if accent != CEDILLA => DOTLESS_I,
+        (false, 'j') if accent != CEDILLA => DOTLESS_J,
+        (false, _) => LOWERCASE_START + index,
+        (true, _) => UPPERCASE_START + index,
+    };
+    return Some(vec![base, mark]);
+}
+
+/// Every glyph code that stands for a character.
+fn character_glyph_codes() -> impl Iterator<Item=GlyphCode> {
+    (0..0x4000).filter(|&glyph| glyphcode::as_char(glyph).is_some())
+}
+
+/// How a `PartTable` draws glyph codes it has no parts for.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MissingCharacter {
+    /// A frame around the code's 8 hex digits. The digit pieces are 16*8
+    /// parts starting at `digits`: one for each digit at each of the 8
+    /// positions inside the frame, two to a row, most significant first.
+    Hex { frame: u32, digits: u32 },
+    /// Just the frame.
+    Frame(u32),
+}
+
+/// Maps glyph codes to the atlas parts they are drawn from, in drawing order.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PartTable {
+    parts: HashMap<GlyphCode, Vec<u32>>,
+    missing: MissingCharacter,
+}
+
+impl PartTable {
+    /// A table with no glyphs, drawing everything as `missing`.
+    pub fn new(missing: MissingCharacter) -> PartTable {
+        PartTable {
+            parts: HashMap::new(),
+            missing: missing,
+        }
+    }
+
+    /// The table for the built-in atlas.
+    pub fn builtin() -> PartTable {
+        let mut table = PartTable::new(MissingCharacter::Hex {
+            frame: MISSING_CHARACTER_FRAME,
+            digits: MISSING_CHARACTER_PIECES_START,
+        });
+        for &(chars, start) in BUILTIN_RUNS.iter() {
+            for (part, ch) in (start..).zip(chars.chars()) {
+                table.insert(glyphcode::from_char(ch).unwrap(), vec![part]);
+            }
+        }
+        for glyph in character_glyph_codes().filter(|glyph| glyph & ACCENT_MASK != 0 && *glyph >= 0x1000) {
+            if let Some(parts) = accented_letter_parts(glyph) {
+                table.insert(glyph, parts);
+            }
+        }
+        return table;
+    }
+
+    /// A table mapping every glyph code that stands for a character to
+    /// `part_for(character)`, where that returns a part.
+    pub fn from_chars<F>(missing: MissingCharacter, part_for: F) -> PartTable
+        where F: Fn(char) -> Option<u32>
+    {
+        let mut table = PartTable::new(missing);
+        for glyph in character_glyph_codes() {
+            if let Some(part) = glyphcode::as_char(glyph).and_then(&part_for) {
+                table.insert(glyph, vec![part]);
+            }
+        }
+        return table;
+    }
 
+    /// Draws `glyph` from `parts`, replacing any previous mapping.
+    pub fn insert(&mut self, glyph: GlyphCode, parts: Vec<u32>) {
+        self.parts.insert(glyph, parts);
+    }
+
+    pub fn remove(&mut self, glyph: GlyphCode) {
+        self.parts.remove(&glyph);
+    }
+
+    /// The parts `glyph` is mapped to, if it has any.
+    pub fn get(&self, glyph: GlyphCode) -> Option<&[u32]> {
+        self.parts.get(&glyph).map(|parts| &parts[..])
+    }
+
+    pub fn missing(&self) -> MissingCharacter {
+        self.missing
+    }
+
+    /// Number of glyph codes with parts.
+    pub fn len(&self) -> usize {
+        self.parts.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parts.is_empty()
+    }
+
+    /// The parts to draw for `glyph`, falling back to the missing-character
+    /// box.
+    pub fn parts(&self, glyph: GlyphCode) -> Vec<u32> {
+        if let Some(parts) = self.get(glyph) {
+            return parts.to_vec();
+        }
+
+        match self.missing {
+            MissingCharacter::Frame(frame) => vec![frame],
+            MissingCharacter::Hex { frame, digits } => {
+                let mut parts = vec![frame];
+                for position in 0..8 {
+                    let digit = (glyph >> (28 - 4*position)) & 0xf;
+                    parts.push(digits + position*16 + digit);
+                }
+                parts
+            }
+        }
+    }
+}
+
+/// The table for the built-in atlas, built on first use.
+pub fn builtin_table() -> &'static PartTable {
+    static BUILTIN: OnceLock<PartTable> = OnceLock::new();
+    BUILTIN.get_or_init(PartTable::builtin)
+}
+
+/// The parts of `glyph` in the built-in atlas.
 pub fn glyph_to_parts(glyph: u32) -> Vec<u32> {
-    let simple = match glyph {
-        0 => SYMBOLS_1,
-        1 => SYMBOLS_1 + 1,
-        2 => SYMBOLS_1 + 2,
-        3 => SYMBOLS_1 + 3,
-        4 => SYMBOLS_1 + 4,
-        5 => SYMBOLS_1 + 5,
-        6 => SYMBOLS_1 + 6,
-        7 => SYMBOLS_1 + 7,
-        8 => SYMBOLS_1 + 8,
-        9 => SYMBOLS_1 + 9,
-
-        10 => DIGITS_START,
-        11 => DIGITS_START + 1,
-        12 => DIGITS_START + 2,
-        13 => DIGITS_START + 3,
-        14 => DIGITS_START + 4,
-        15 => DIGITS_START + 5,
-        16 => DIGITS_START + 6,
-        17 => DIGITS_START + 7,
-        18 => DIGITS_START + 8,
-        19 => DIGITS_START + 10,
-        _ => {
-            return vec![0, 1, 19];
+    return builtin_table().parts(glyph);
+}
+
+#[cfg(test)]
+mod test {
+    use super::{glyph_to_parts, MissingCharacter, PartTable};
+    use atlas::GlyphAtlas;
+    use glyphcode;
+
+    #[test]
+    fn printable_ascii_has_parts() {
+        let atlas = GlyphAtlas::builtin();
+        let mut seen = Vec::new();
+        for ch in (0x20u8..0x7f).map(|b| b as char) {
+            let parts = glyph_to_parts(glyphcode::from_char(ch).unwrap());
+            assert_eq!(parts.len(), 1, "{:?} has no part", ch);
+            assert!(parts[0] < atlas.part_count());
+            assert!(!seen.contains(&parts[0]), "{:?} shares a part", ch);
+            seen.push(parts[0]);
         }
-    };
+    }
+
+    #[test]
+    fn letters() {
+        assert_eq!(glyph_to_parts(0x1000), glyph_to_parts(glyphcode::from_char('a').unwrap()));
+        assert_eq!(glyph_to_parts(0x3190), vec![174]);
+        assert_eq!(glyph_to_parts(0x100f).len(), 9);
+        assert_eq!(glyph_to_parts(0x11a0).len(), 9);
+    }
+
+    #[test]
+    fn missing_characters() {
+        assert_eq!(glyph_to_parts(0x1234abcd), vec![0, 1 + 1, 17 + 2, 33 + 3, 49 + 4, 65 + 10, 81 + 11, 97 + 12, 113 + 13]);
+        assert_eq!(glyph_to_parts(0xfffd), vec![0, 1, 17, 33, 49, 65 + 15, 81 + 15, 97 + 15, 113 + 13]);
+    }
+
+    #[test]
+    fn accents() {
+        let atlas = GlyphAtlas::builtin();
+        let parts = |ch| glyph_to_parts(glyphcode::from_char(ch).unwrap());
+        let e = parts('e')[0];
+
+        assert_eq!(parts('\u{e9}'), vec![e, 203]);
+        assert_eq!(parts('\u{c9}'), vec![parts('E')[0], 210]);
+        // Accented i and j drop their dots; ascenders take the high marks.
+        assert_eq!(parts('\u{ef}'), vec![201, 206]);
+        assert_eq!(parts('\u{10f}'), vec![parts('d')[0], 215]);
+        assert_eq!(parts('\u{e7}'), vec![parts('c')[0], 242]);
+
+        for ch in "\u{e1}\u{e0}\u{e2}\u{e4}\u{e3}\u{e7}\u{e5}\u{1ce}\u{101}\u{c5}\u{ce}".chars() {
+            let parts = parts(ch);
+            assert_eq!(parts.len(), 2, "{:?}", ch);
+            assert!(parts.iter().all(|&part| part < atlas.part_count()));
+        }
+    }
 
-    return vec![simple];
+    #[test]
+    fn tables() {
+        let mut table = PartTable::from_chars(MissingCharacter::Frame(7), |ch| if ch == 'b' { Some(3) } else { None });
+        assert_eq!(table.len(), 1);
+        assert_eq!(table.parts(glyphcode::from_char('b').unwrap()), vec![3]);
+        assert_eq!(table.parts(glyphcode::from_char('c').unwrap()), vec![7]);
 
+        // Codes without a character can be given parts too.
+        table.insert(0x8000, vec![1, 2]);
+        assert_eq!(table.get(0x8000), Some(&[1, 2][..]));
+        table.remove(0x8000);
+        assert_eq!(table.parts(0x8000), vec![7]);
+    }
 }
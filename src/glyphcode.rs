@@ -1,7 +1,67 @@
-use unicode_segmentation::UnicodeSegmentation;
+use std::error::Error;
+use std::fmt;
+use std::iter::Enumerate;
+use std::slice;
+
+use unicode_segmentation::{GraphemeIndices, UnicodeSegmentation};
 
 pub type GlyphCode = u32;
 
+/// Stands in for characters that have no glyph code. It is deliberately not
+/// mapped to any character, so it is drawn as the missing-character box.
+pub const REPLACEMENT: GlyphCode = 0xfffd;
+
+/// Accents are kept in the low four bits of a letter's glyph code, so
+/// `from_char('e') | ACUTE` is 'é'. Only combinations that Unicode has a
+/// precomposed character for are valid.
+pub const ACUTE: GlyphCode = 1;
+pub const GRAVE: GlyphCode = 2;
+pub const CIRCUMFLEX: GlyphCode = 3;
+pub const DIAERESIS: GlyphCode = 4;
+pub const TILDE: GlyphCode = 5;
+pub const CEDILLA: GlyphCode = 6;
+pub const RING: GlyphCode = 7;
+pub const CARON: GlyphCode = 8;
+pub const MACRON: GlyphCode = 9;
+
+pub const ACCENT_MASK: GlyphCode = 0x0f;
+
+/// For each accent: its combining mark, the letters that take it, and the
+/// precomposed characters for those letters in the same order.
+const ACCENTED: [(GlyphCode, char, &str, &str); 9] = [
+    (ACUTE, '\u{301}', "acegiklmnoprsuwyzACEGIKLMNOPRSUWYZ", "áćéǵíḱĺḿńóṕŕśúẃýźÁĆÉǴÍḰĹḾŃÓṔŔŚÚẂÝŹ"),
+    (GRAVE, '\u{300}', "aeinouwyAEINOUWY", "àèìǹòùẁỳÀÈÌǸÒÙẀỲ"),
+    (CIRCUMFLEX, '\u{302}', "aceghijosuwyzACEGHIJOSUWYZ", "âĉêĝĥîĵôŝûŵŷẑÂĈÊĜĤÎĴÔŜÛŴŶẐ"),
+    (DIAERESIS, '\u{308}', "aehiotuwxyAEHIOUWXY", "äëḧïöẗüẅẍÿÄËḦÏÖÜẄẌŸ"),
+    (TILDE, '\u{303}', "aeinouvyAEINOUVY", "ãẽĩñõũṽỹÃẼĨÑÕŨṼỸ"),
+    (CEDILLA, '\u{327}', "cdeghklnrstCDEGHKLNRST", "çḑȩģḩķļņŗşţÇḐȨĢḨĶĻŅŖŞŢ"),
+    (RING, '\u{30a}', "auwyAU", "åůẘẙÅŮ"),
+    (CARON, '\u{30c}', "acdeghijklnorstuzACDEGHIKLNORSTUZ", "ǎčďěǧȟǐǰǩľňǒřšťǔžǍČĎĚǦȞǏǨĽŇǑŘŠŤǓŽ"),
+    (MACRON, '\u{304}', "aegiouyAEGIOUY", "āēḡīōūȳĀĒḠĪŌŪȲ"),
+];
+
+fn accented_from_char(ch: char) -> Option<GlyphCode> {
+    for &(accent, _, bases, precomposed) in ACCENTED.iter() {
+        if let Some(index) = precomposed.chars().position(|c| c == ch) {
+            let base = bases.chars().nth(index)?;
+            return from_char(base).map(|code| code | accent);
+        }
+    }
+    None
+}
+
+fn accented_as_char(base: char, accent: GlyphCode) -> Option<char> {
+    let &(_, _, bases, precomposed) = ACCENTED.iter().find(|entry| entry.0 == accent)?;
+    let index = bases.chars().position(|c| c == base)?;
+    precomposed.chars().nth(index)
+}
+
+/// The letter glyph code for `base` followed by the combining mark `mark`.
+fn compose(base: char, mark: char) -> Option<GlyphCode> {
+    let &(accent, _, _, _) = ACCENTED.iter().find(|entry| entry.1 == mark)?;
+    accented_as_char(base, accent)?;
+    return from_char(base).map(|code| code | accent);
+}
 
 pub fn from_char(ch: char) -> Option<u32> {
     if ch >= 'a' && ch <='z' {
@@ -45,7 +105,22 @@ pub fn from_char(ch: char) -> Option<u32> {
         '=' => Some(27),
         '+' => Some(28),
         
-        _ => None
+        '?' => Some(29),
+        '(' => Some(30),
+        ')' => Some(31),
+        '[' => Some(32),
+        ']' => Some(33),
+        '{' => Some(34),
+        '}' => Some(35),
+        '<' => Some(36),
+        '>' => Some(37),
+        '"' => Some(38),
+        '\'' => Some(39),
+        '`' => Some(40),
+        '|' => Some(41),
+        '~' => Some(42),
+        
+        _ => accented_from_char(ch)
     }
 }
 
@@ -53,15 +128,19 @@ pub fn as_char(character: GlyphCode) -> Option<char> {
     let lower_a_code = 0x1000;
     let after_lowers = lower_a_code + 26*16;
     
-    if lower_a_code <= character && character  < after_lowers && (character & 0x0f)==0 {
-        return Some(((('a' as u32) + ((character & 0x0ff0)>>4)) as u8) as char);
+    let accent = character & ACCENT_MASK;
+    
+    if lower_a_code <= character && character  < after_lowers {
+        let base = ((('a' as u32) + ((character & 0x0ff0)>>4)) as u8) as char;
+        return if accent == 0 { Some(base) } else { accented_as_char(base, accent) };
     }
     
     let case_mask = 0x00002000;
     let upper_a_code = lower_a_code ^ case_mask;
     let after_uppers = after_lowers ^ case_mask;
-    if upper_a_code <= character && character < after_uppers && (character & 0x0f)==0 {
-        return Some(((('A' as u32) + ((character & 0x0ff0)>>4)) as u8) as char);
+    if upper_a_code <= character && character < after_uppers {
+        let base = ((('A' as u32) + ((character & 0x0ff0)>>4)) as u8) as char;
+        return if accent == 0 { Some(base) } else { accented_as_char(base, accent) };
     }
     
     Some(match character {
@@ -97,42 +176,149 @@ pub fn as_char(character: GlyphCode) -> Option<char> {
         27 => '=',
         28 => '+',
         
+        29 => '?',
+        30 => '(',
+        31 => ')',
+        32 => '[',
+        33 => ']',
+        34 => '{',
+        35 => '}',
+        36 => '<',
+        37 => '>',
+        38 => '"',
+        39 => '\'',
+        40 => '`',
+        41 => '|',
+        42 => '~',
+        
         _ => { return None; },
     })
 }
 
-pub fn to_string(glyphcodes: &[u32]) -> Option<String> {
-    let mut accum = String::new();
-    for glyphcode in glyphcodes.iter() {
-        if let Some(ch) = as_char(*glyphcode) {
-            accum.push(ch);
-        } else  {
-            return None;
-        }
+/// The glyph code for a single grapheme, if it has one. A letter followed by
+/// a combining accent is treated like the precomposed character.
+pub fn from_grapheme(grapheme: &str) -> Option<GlyphCode> {
+    let mut chars = grapheme.chars();
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(ch), None, None) => from_char(ch),
+        (Some(base), Some(mark), None) => compose(base, mark),
+        _ => None,
     }
-    return Some(accum);
 }
 
-pub fn from_str(s: &str) -> Option<Vec<u32>> {
-    let mut accum = Vec::new();
-    
-    for grapheme in UnicodeSegmentation::graphemes(s, true) {
-        if grapheme.len()!=1 {
-            return None;
-        }
-        if let Some(glyphcode) = grapheme.chars().next().and_then(|x| from_char(x)) {
-            accum.push(glyphcode);
-        } else {
-            return None;
-        }
+/// A grapheme that has no glyph code.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EncodeError {
+    pub grapheme: String,
+    /// Byte offset of the grapheme in the string being converted.
+    pub offset: usize,
+}
+
+impl fmt::Display for EncodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no glyph for {:?} at byte {}", self.grapheme, self.offset)
     }
-    return Some(accum);
 }
-    
+
+impl Error for EncodeError {}
+
+/// A glyph code that has no character.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DecodeError {
+    pub glyphcode: GlyphCode,
+    /// Position of the glyph code in the slice being converted.
+    pub index: usize,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "no character for glyph code {:#x} at index {}", self.glyphcode, self.index)
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Iterator over the glyph codes of a string's graphemes. See `encode`.
+pub struct Encoder<'a> {
+    graphemes: GraphemeIndices<'a>,
+}
+
+impl<'a> Iterator for Encoder<'a> {
+    type Item = Result<GlyphCode, EncodeError>;
+
+    fn next(&mut self) -> Option<Result<GlyphCode, EncodeError>> {
+        self.graphemes.next().map(|(offset, grapheme)| {
+            from_grapheme(grapheme).ok_or_else(|| EncodeError { grapheme: grapheme.to_string(), offset: offset })
+        })
+    }
+}
+
+/// Iterator over the characters of glyph codes. See `decode`.
+pub struct Decoder<'a> {
+    glyphcodes: Enumerate<slice::Iter<'a, GlyphCode>>,
+}
+
+impl<'a> Iterator for Decoder<'a> {
+    type Item = Result<char, DecodeError>;
+
+    fn next(&mut self) -> Option<Result<char, DecodeError>> {
+        self.glyphcodes.next().map(|(index, &glyphcode)| {
+            as_char(glyphcode).ok_or(DecodeError { glyphcode: glyphcode, index: index })
+        })
+    }
+}
+
+/// Converts `s` one grapheme at a time, without allocating.
+pub fn encode(s: &str) -> Encoder<'_> {
+    Encoder { graphemes: s.grapheme_indices(true) }
+}
+
+/// Converts `glyphcodes` one at a time, without allocating.
+pub fn decode(glyphcodes: &[GlyphCode]) -> Decoder<'_> {
+    Decoder { glyphcodes: glyphcodes.iter().enumerate() }
+}
+
+/// Like `encode`, but graphemes without a glyph code become `REPLACEMENT`.
+pub fn encode_lossy(s: &str) -> impl Iterator<Item=GlyphCode> + '_ {
+    encode(s).map(|result| result.unwrap_or(REPLACEMENT))
+}
+
+/// Like `decode`, but glyph codes without a character become U+FFFD.
+pub fn decode_lossy(glyphcodes: &[GlyphCode]) -> impl Iterator<Item=char> + '_ {
+    decode(glyphcodes).map(|result| result.unwrap_or('\u{fffd}'))
+}
+
+/// Converts `s`, failing at the first grapheme without a glyph code.
+pub fn try_from_str(s: &str) -> Result<Vec<GlyphCode>, EncodeError> {
+    encode(s).collect()
+}
+
+/// Converts `glyphcodes`, failing at the first one without a character.
+pub fn try_to_string(glyphcodes: &[GlyphCode]) -> Result<String, DecodeError> {
+    decode(glyphcodes).collect()
+}
+
+pub fn from_str_lossy(s: &str) -> Vec<GlyphCode> {
+    encode_lossy(s).collect()
+}
+
+pub fn to_string_lossy(glyphcodes: &[GlyphCode]) -> String {
+    decode_lossy(glyphcodes).collect()
+}
+
+pub fn to_string(glyphcodes: &[u32]) -> Option<String> {
+    try_to_string(glyphcodes).ok()
+}
+
+pub fn from_str(s: &str) -> Option<Vec<u32>> {
+    try_from_str(s).ok()
+}
 
 #[cfg(test)]
 mod test {
-    use super::{from_str, to_string};
+    use super::{decode, encode, from_str, from_str_lossy, to_string, to_string_lossy, try_from_str, try_to_string};
+    use super::{as_char, from_char, from_grapheme, DecodeError, EncodeError, REPLACEMENT};
+    use super::{ACCENTED, ACUTE, TILDE};
 
     fn test_str(s: &str, expected: &[u32]) {
         assert_eq!(from_str(s), Some(expected.to_vec()));
@@ -144,4 +330,73 @@ mod test {
         test_str("abc", &[0x1000, 0x1010, 0x1020]);
         test_str("Abc", &[0x3000, 0x1010, 0x1020]);
     }
+
+    #[test]
+    fn printable_ascii_round_trip() {
+        let mut seen = Vec::new();
+        for ch in (0x20u8..0x7f).map(|b| b as char) {
+            let glyphcode = from_char(ch).unwrap_or_else(|| panic!("no glyph code for {:?}", ch));
+            assert_eq!(as_char(glyphcode), Some(ch));
+            assert!(!seen.contains(&glyphcode), "{:?} shares a glyph code", ch);
+            seen.push(glyphcode);
+        }
+        assert_eq!(seen.len(), 95);
+    }
+
+    #[test]
+    fn accents() {
+        assert_eq!(from_char('\u{e9}'), Some(from_char('e').unwrap() | ACUTE));
+        assert_eq!(from_char('\u{d1}'), Some(from_char('N').unwrap() | TILDE));
+        test_str("\u{e9}\u{f1}\u{fc}\u{e7}\u{e5}\u{161}", &[0x1041, 0x10d5, 0x1144, 0x1026, 0x1007, 0x1128]);
+
+        // Combining sequences become the precomposed character.
+        assert_eq!(from_str("e\u{301}S\u{30c}"), from_str("\u{e9}\u{160}"));
+        assert_eq!(to_string(&from_str("a\u{30a}").unwrap()), Some("\u{e5}".to_string()));
+
+        for &(accent, mark, bases, precomposed) in ACCENTED.iter() {
+            assert_eq!(bases.chars().count(), precomposed.chars().count());
+            for (base, ch) in bases.chars().zip(precomposed.chars()) {
+                let glyphcode = from_char(ch).unwrap();
+                assert_eq!(glyphcode, from_char(base).unwrap() | accent);
+                assert_eq!(as_char(glyphcode), Some(ch));
+                assert_eq!(from_grapheme(&format!("{}{}", base, mark)), Some(glyphcode));
+            }
+        }
+    }
+
+    #[test]
+    fn encode_errors() {
+        let err = try_from_str("ab\u{df}c").unwrap_err();
+        assert_eq!(err, EncodeError { grapheme: "\u{df}".to_string(), offset: 2 });
+        assert_eq!(err.to_string(), "no glyph for \"\u{df}\" at byte 2");
+
+        // Combining sequences are reported whole.
+        let err = try_from_str("x q\u{301}").unwrap_err();
+        assert_eq!(err, EncodeError { grapheme: "q\u{301}".to_string(), offset: 2 });
+    }
+
+    #[test]
+    fn decode_errors() {
+        assert_eq!(try_to_string(&[0x1000, 0xfffd]), Err(DecodeError { glyphcode: 0xfffd, index: 1 }));
+        assert_eq!(try_to_string(&[0x1000, 0x100f]), Err(DecodeError { glyphcode: 0x100f, index: 1 }));
+        // There is no precomposed q with an acute.
+        assert_eq!(try_to_string(&[0x1101]), Err(DecodeError { glyphcode: 0x1101, index: 0 }));
+    }
+
+    #[test]
+    fn lossy() {
+        assert_eq!(from_str_lossy("a\u{1F600}b"), [0x1000, REPLACEMENT, 0x1010]);
+        assert_eq!(to_string_lossy(&[0x1000, 0xffff, 0x1010]), "a\u{fffd}b");
+    }
+
+    #[test]
+    fn iterators() {
+        let mut encoder = encode("a\u{df}");
+        assert_eq!(encoder.next(), Some(Ok(0x1000)));
+        assert!(encoder.next().unwrap().is_err());
+        assert_eq!(encoder.next(), None);
+
+        let chars: Vec<_> = decode(&[0x3000, 0]).collect();
+        assert_eq!(chars, [Ok('A'), Ok(' ')]);
+    }
 }
@@ -19,6 +19,7 @@
 use std::ptr;
 use std::cell::{RefCell, BorrowState};
 use std::sync::mpsc::{channel, Sender, Receiver};
+use std::sync::{Arc, Mutex};
 use std::thread;
 
 use std::ops::{Deref, DerefMut};
@@ -26,85 +27,106 @@ use std::ops::{Deref, DerefMut};
 use libc::{c_int};
 
 use windows::main_window_loop;
-use winapi::{UINT, HBRUSH, COLORREF, LPARAM, WPARAM, LRESULT};
-use user32::{PostQuitMessage, GetSysColor};
+use winapi::{UINT, HBRUSH, COLORREF, LPARAM, WPARAM, LRESULT, POINT, RECT, TRACKMOUSEEVENT, DWORD, MINMAXINFO, HDC};
+use winapi::{BITMAPINFO, BITMAPINFOHEADER, RGBQUAD};
+use user32::{PostQuitMessage, GetSysColor, GetKeyState, ScreenToClient, SetCapture, ReleaseCapture, TrackMouseEvent};
+use user32::{AdjustWindowRectEx, SetWindowTextW, InvalidateRect, GetUpdateRect, SetTimer, KillTimer};
 use winapi::{CREATESTRUCTW};
-use gdi32::{GetStockObject, SetDCBrushColor};
+use gdi32::{GetStockObject, SetDCBrushColor, SetDIBitsToDevice};
 use windows::instance::Instance;
 use windows::resource::*;
 use windows::window::{WindowImpl, Window, WndClass, WindowParams};
 use windows::window::{OnCreate, OnSize, OnDestroy, OnPaint, OnEraseBackground, OnMessage};
 use windows::window::{OnLeftButtonDown, OnLeftButtonUp, OnKeyDown, OnKeyUp};
 use windows::window;
-use windows::gdi::{PaintDc, MemoryDc};
-use windows::font::Font;
-use windows::font;
-use windows::font::{Family, Pitch, Quality, CharSet, OutputPrecision, ClipPrecision, FontAttr};
-use glyphcode;
-use input_event::InputEvent;
-use screen::{Glyph, Screen};
+use windows::gdi::PaintDc;
+use atlas::GlyphAtlas;
+use color::Palette;
+use headless::{draw_cursor, draw_glyph, Framebuffer};
+use input_event::{InputEvent, Key, Modifiers, MouseButton, MousePosition};
+use interface::GridUiInterface;
+use config::GridUiConfig;
+use screen::{Cursor, Damage, Glyph, Screen, ScreenUpdate};
 
 
 struct MainFrameState {
     screen: Screen,
+    palette: Palette,
+    /// Whether blinking glyphs are in their visible phase.
+    blink_visible: bool,
+    /// Whether BLINK_TIMER is running, which it only is while some glyph
+    /// blinks.
+    blink_timer: bool,
+    cursor: Cursor,
+    /// Whether the cursor is in the visible phase of its blinking.
+    cursor_blink_visible: bool,
     announced_grid_size: (i32, i32),
+    /// The first half of a UTF-16 surrogate pair, waiting for its WM_CHAR partner.
+    high_surrogate: Option<u16>,
+    /// Whether a MouseEnter has been sent without a matching MouseLeave.
+    mouse_inside: bool,
+    /// Wheel movement, in 1/120ths of a notch, not yet reported because it
+    /// doesn't add up to a whole notch. Horizontal, then vertical.
+    wheel_remainder: (i32, i32),
 }
 
 struct MainFrame {
     win: Window,
-    font: RefCell<Option<Font>>,
+    atlas: GlyphAtlas,
     input_sink: Sender<InputEvent>,
-    screen_source: Receiver<Screen>,
+    screen_source: Receiver<ScreenUpdate>,
+    palette_source: Receiver<Palette>,
+    cursor_source: Receiver<Cursor>,
+    grid_width: u32,
     grid_height: u32,
+    config: GridUiConfig,
+    /// The window style, needed to convert between client and window sizes.
+    style: DWORD,
     state: RefCell<MainFrameState>,
+    /// The last announced grid size, shared with `WindowsGridUi::grid_size`.
+    grid_size: Arc<Mutex<(u32, u32)>>,
 }
 
 const WM_CHECK_SCREENS : UINT = 0x0401;
+const WM_TIMER : UINT = 0x0113;
+/// Timer ID for blinking glyphs.
+const BLINK_TIMER : usize = 1;
+/// Timer ID for the blinking cursor.
+const CURSOR_TIMER : usize = 2;
+const WM_CHAR : UINT = 0x0102;
+const WM_MOUSEMOVE : UINT = 0x0200;
+const WM_RBUTTONDOWN : UINT = 0x0204;
+const WM_RBUTTONUP : UINT = 0x0205;
+const WM_MBUTTONDOWN : UINT = 0x0207;
+const WM_MBUTTONUP : UINT = 0x0208;
+const WM_MOUSEWHEEL : UINT = 0x020A;
+const WM_XBUTTONDOWN : UINT = 0x020B;
+const WM_XBUTTONUP : UINT = 0x020C;
+const WM_MOUSEHWHEEL : UINT = 0x020E;
+const WM_MOUSELEAVE : UINT = 0x02A3;
+const WM_GETMINMAXINFO : UINT = 0x0024;
+const WHEEL_DELTA : i32 = 120;
+const WS_THICKFRAME : DWORD = 0x00040000;
+const WS_MAXIMIZEBOX : DWORD = 0x00010000;
 
 wnd_proc!(MainFrame, win, WM_CREATE, WM_DESTROY, WM_LBUTTONDOWN, WM_LBUTTONUP, WM_KEYDOWN, WM_KEYUP, WM_SIZE, WM_PAINT, WM_ERASEBKGND, ANY);
 
 impl OnCreate for MainFrame {
     fn on_create(&self, _cs: &CREATESTRUCTW) -> bool {
-        let font_attr = FontAttr {
-            height: self.grid_height as isize,
-            width: (self.grid_height/2) as isize,
-            escapement: 0,
-            orientation: 0,
-            weight: 600, // FW_NORMAL. TODO use FW_DONTCARE (0)?
-            italic: false,
-            underline: false,
-            strike_out: false,
-            char_set: CharSet::DEFAULT_CHARSET,
-            output_precision: OutputPrecision::OUT_DEFAULT_PRECIS,
-            clip_precision: ClipPrecision::CLIP_DEFAULT_PRECIS,
-            quality: Quality::ANTIALIASED_QUALITY,
-            pitch: Pitch::DEFAULT_PITCH,
-            family: Family::FF_DONTCARE,
-            face: Some("Courier New".to_string()),
-        };
-        
-        let font = font::Font::new(&font_attr);
-        
-        match font {
-            None => false,
-            Some(f) => {
-                *self.font.borrow_mut() = Some(f);
-                true
-            }
-        }
+        true
     }
 }
 
 impl OnSize for MainFrame {
     fn on_size(&self, width: isize, height: isize) {
-        let grid_width = self.grid_height/2;
-        let cols = width as u32 / grid_width as u32;
+        let cols = width as u32 / self.grid_width;
         let rows = height as u32 / self.grid_height as u32;
         
         let size = (cols as i32, rows as i32);
         self.with_state_mut(|state: &mut MainFrameState| {
             if size != state.announced_grid_size {
                 state.announced_grid_size = size;
+                *self.grid_size.lock().unwrap() = (cols, rows);
                 self.send_event(InputEvent::Size(cols, rows));
             }
         });
@@ -124,34 +146,51 @@ impl OnPaint for MainFrame {
     fn on_paint(&self) {
         //note: VirtualAlloc the buffer!
         
-        let font = self.font.borrow();
+        // Only cells overlapping this need painting. BeginPaint empties it,
+        // so read it first.
+        let mut update_rect = RECT { left: 0, top: 0, right: 0, bottom: 0 };
+        unsafe { GetUpdateRect(self.win.wnd, &mut update_rect, 0); }
+
         let pdc = PaintDc::new(self).expect("Paint DC");
-        pdc.dc.select_font(&font.expect("font is empty"));
-        
-        let bmp = pdc.dc.create_compatible_bitmap(50,50);
-        let mem_dc = MemoryDc::new(&pdc.dc).unwrap();
-        mem_dc.dc.select_bitmap(&bmp);
-        //mem_dc.fill_rect((0,0), (5,10), 
         
         self.with_state(|state: & MainFrameState| {
             let ref screen = state.screen;
-            let grid_width = self.grid_height/2;
+            let grid_width = self.grid_width;
             if state.screen.width > 0 {
-                for (row_idx, row) in screen.glyphs.chunks(screen.width as usize).enumerate() {
-                    for (col_idx, cell) in row.iter().enumerate() {
-                        pdc.dc.set_text_color(cell.foreground as COLORREF);
-                        pdc.dc.set_background_color(cell.background as COLORREF);
-                        
-                        let mut char_str = String::new();
-                        char_str.push(glyphcode::as_char(cell.character).unwrap_or(' '));
-                        pdc.dc.text_out((col_idx*grid_width as usize) as isize, (row_idx*(self.grid_height as usize)) as isize, &char_str[..]);
+                let rows = screen.height();
+                let first_col = (update_rect.left.max(0) as u32 / grid_width).min(screen.width);
+                let last_col = (update_rect.right.max(0) as u32).div_ceil(grid_width).min(screen.width);
+                let first_row = (update_rect.top.max(0) as u32 / self.grid_height).min(rows);
+                let last_row = (update_rect.bottom.max(0) as u32).div_ceil(self.grid_height).min(rows);
+
+                // Draw the cells that need it off screen, then copy them
+                // over in one go.
+                let mut framebuffer = Framebuffer::new((last_col - first_col) * grid_width, (last_row - first_row) * self.grid_height);
+                let cursor = state.cursor;
+                let cursor_shown = cursor.visible && state.cursor_blink_visible;
+                for row in first_row..last_row {
+                    for col in first_col..last_col {
+                        let (left, top) = ((col - first_col) * grid_width, (row - first_row) * self.grid_height);
+                        // The end of a partial last row is left empty.
+                        let cell = match screen.get(col, row) {
+                            Some(cell) => cell.as_shown(state.blink_visible),
+                            None => { continue; }
+                        };
+                        if cursor_shown && (cursor.col, cursor.row) == (col, row) {
+                            let cell = cursor.over(cell);
+                            draw_glyph(&self.atlas, &state.palette, &mut framebuffer, &cell, left, top);
+                            draw_cursor(&self.atlas, &state.palette, &mut framebuffer, cursor.shape, &cell, left, top);
+                        } else {
+                            draw_glyph(&self.atlas, &state.palette, &mut framebuffer, &cell, left, top);
+                        }
                     }
                 }
+                blit(pdc.dc.raw, (first_col * grid_width) as i32, (first_row * self.grid_height) as i32, &framebuffer);
             }
             
             if let Some(client_rect) = self.win.client_rect(){
                 let max_filled_x = screen.width * grid_width;
-                let max_filled_y = if screen.width>0 { (screen.glyphs.len() as u32 / screen.width) * self.grid_height } else { 0 };
+                let max_filled_y = screen.height() * self.grid_height;
                 
                 let filler_color = unsafe { GetSysColor(15 /* COLOR_3DFACE */) as COLORREF };
                 unsafe { SetDCBrushColor(pdc.dc.raw, filler_color) };
@@ -176,39 +215,116 @@ impl OnEraseBackground for MainFrame {
 
 impl OnLeftButtonDown for MainFrame {
     fn on_left_button_down(&self, x: isize, y: isize, _flags: u32) {
-        let grid_width = self.grid_height/2;
-        let col = (x as u32) / (grid_width as u32);
-        let row = (y as u32) / (self.grid_height as u32);
-        self.send_event(InputEvent::MouseDown(col as u32, row as u32));
+        self.on_button(MouseButton::Left, true, x as i32, y as i32);
     }
 }
 
 impl OnLeftButtonUp for MainFrame {
     fn on_left_button_up(&self, x: isize, y: isize, _flags: u32) {
-        let grid_width = self.grid_height/2;
-        let col = (x as u32) / (grid_width as u32);
-        let row = (y as u32) / (self.grid_height as u32);
-        self.send_event(InputEvent::MouseUp(col as u32, row as u32));
+        self.on_button(MouseButton::Left, false, x as i32, y as i32);
+    }
+}
+
+/// Copies `framebuffer` to `dc` with its top left corner at (left, top).
+fn blit(dc: HDC, left: i32, top: i32, framebuffer: &Framebuffer) {
+    if framebuffer.width() == 0 || framebuffer.height() == 0 {
+        return;
+    }
+
+    // 32-bit DIBs are BGRA.
+    let mut bgra = framebuffer.pixels().to_vec();
+    for pixel in bgra.chunks_mut(4) {
+        pixel.swap(0, 2);
+    }
+
+    let info = BITMAPINFO {
+        bmiHeader: BITMAPINFOHEADER {
+            biSize: ::std::mem::size_of::<BITMAPINFOHEADER>() as DWORD,
+            biWidth: framebuffer.width() as i32,
+            // Negative for rows stored top to bottom.
+            biHeight: -(framebuffer.height() as i32),
+            biPlanes: 1,
+            biBitCount: 32,
+            biCompression: 0, // BI_RGB
+            biSizeImage: 0,
+            biXPelsPerMeter: 0,
+            biYPelsPerMeter: 0,
+            biClrUsed: 0,
+            biClrImportant: 0,
+        },
+        bmiColors: [RGBQUAD { rgbBlue: 0, rgbGreen: 0, rgbRed: 0, rgbReserved: 0 }],
+    };
+    unsafe {
+        SetDIBitsToDevice(dc, left, top, framebuffer.width(), framebuffer.height(),
+                          0, 0, 0, framebuffer.height(),
+                          bgra.as_ptr() as *const _, &info, 0 /* DIB_RGB_COLORS */);
     }
 }
 
-fn windows_keycode_to_character(keycode: u8) -> Option<u32> {
+/// The outer size of a window with `style` whose client area fits
+/// `grid_size` cells of `cell_size` pixels.
+fn window_size_for_grid(style: DWORD, grid_size: (u32, u32), cell_size: (u32, u32)) -> (isize, isize) {
+    let mut rect = RECT {
+        left: 0,
+        top: 0,
+        right: (grid_size.0 * cell_size.0) as i32,
+        bottom: (grid_size.1 * cell_size.1) as i32,
+    };
+    unsafe { AdjustWindowRectEx(&mut rect, style, 0, 0); }
+    ((rect.right - rect.left) as isize, (rect.bottom - rect.top) as isize)
+}
+
+/// The signed client coordinates packed into the LPARAM of a mouse message.
+fn lparam_point(lparam: LPARAM) -> (i32, i32) {
+    ((lparam & 0xffff) as i16 as i32, ((lparam >> 16) & 0xffff) as i16 as i32)
+}
+
+/// The modifier keys held down as of the message being handled.
+fn current_modifiers() -> Modifiers {
+    let is_down = |vk: c_int| unsafe { (GetKeyState(vk) as u16 & 0x8000) != 0 };
+
+    let mut modifiers = Modifiers::NONE;
+    if is_down(0x10 /* VK_SHIFT */) { modifiers = modifiers | Modifiers::SHIFT; }
+    if is_down(0x11 /* VK_CONTROL */) { modifiers = modifiers | Modifiers::CONTROL; }
+    if is_down(0x12 /* VK_MENU */) { modifiers = modifiers | Modifiers::ALT; }
+    if is_down(0x5B /* VK_LWIN */) || is_down(0x5C /* VK_RWIN */) { modifiers = modifiers | Modifiers::SUPER; }
+    modifiers
+}
+
+fn windows_keycode_to_key(keycode: u8) -> Option<Key> {
     if ('A' as u8) <= keycode && keycode <= ('Z' as u8) {
-        return Some(0x1000 + (((keycode - ('A' as u8)) as u32) << 4));
+        return Some(Key::Character(0x1000 + (((keycode - ('A' as u8)) as u32) << 4)));
     } else if ('0' as u8) <= keycode && keycode <= ('9' as u8) {
-        return Some(10 + (keycode - ('0' as u8)) as u32);
+        return Some(Key::Character(10 + (keycode - ('0' as u8)) as u32));
     } else if (' ' as u8) == keycode {
-        return Some(0);
+        return Some(Key::Character(0));
+    } else if 0x70 /* VK_F1 */ <= keycode && keycode <= 0x7B /* VK_F12 */ {
+        return Some(Key::Function(keycode - 0x70 + 1));
     }
-    return None;
+
+    Some(match keycode {
+        0x26 => Key::Up,       // VK_UP
+        0x28 => Key::Down,     // VK_DOWN
+        0x25 => Key::Left,     // VK_LEFT
+        0x27 => Key::Right,    // VK_RIGHT
+        0x0D => Key::Enter,    // VK_RETURN
+        0x1B => Key::Escape,   // VK_ESCAPE
+        0x09 => Key::Tab,      // VK_TAB
+        0x08 => Key::Backspace,// VK_BACK
+        0x2E => Key::Delete,   // VK_DELETE
+        0x24 => Key::Home,     // VK_HOME
+        0x23 => Key::End,      // VK_END
+        0x21 => Key::PageUp,   // VK_PRIOR
+        0x22 => Key::PageDown, // VK_NEXT
+        _ => { return None; }
+    })
 }
 
 impl OnKeyDown for MainFrame {
-    fn on_key_down(&self, keycode: u8, flags: u32) -> bool {
-        if let Some(character) = windows_keycode_to_character(keycode) {
-            self.send_event(InputEvent::KeyDown(character));
-        } else {
-            println!("Unknown key down {} {}", keycode, flags);
+    fn on_key_down(&self, keycode: u8, _flags: u32) -> bool {
+        // Keys with no `Key` are dropped, as the X11 backend does.
+        if let Some(key) = windows_keycode_to_key(keycode) {
+            self.send_event(InputEvent::KeyDown(key, current_modifiers()));
         }
         
         return true;
@@ -216,27 +332,65 @@ impl OnKeyDown for MainFrame {
 }
 
 impl OnKeyUp for MainFrame {
-    fn on_key_up(&self, keycode: u8, flags: u32) -> bool {
-        if let Some(character) = windows_keycode_to_character(keycode) {
-            self.send_event(InputEvent::KeyUp(character));
-        } else {
-            println!("Unknown key up {} {}", keycode, flags);
+    fn on_key_up(&self, keycode: u8, _flags: u32) -> bool {
+        if let Some(key) = windows_keycode_to_key(keycode) {
+            self.send_event(InputEvent::KeyUp(key, current_modifiers()));
         }
         return true;
     }
 }
 
 impl OnMessage for MainFrame {
-    fn on_message(&self, msg: UINT, _: WPARAM, _: LPARAM) -> Option<LRESULT> {
+    fn on_message(&self, msg: UINT, wparam: WPARAM, lparam: LPARAM) -> Option<LRESULT> {
         if msg==WM_CHECK_SCREENS {
             self.check_for_new_screen();
             return Some(0);
         }
-        None
+        if msg==WM_TIMER && wparam as usize == BLINK_TIMER {
+            self.on_blink();
+            return Some(0);
+        }
+        if msg==WM_TIMER && wparam as usize == CURSOR_TIMER {
+            self.on_cursor_blink();
+            return Some(0);
+        }
+        if msg==WM_CHAR {
+            self.on_char(wparam as u16);
+            return Some(0);
+        }
+
+        if msg==WM_GETMINMAXINFO {
+            self.on_get_min_max_info(unsafe { &mut *(lparam as *mut MINMAXINFO) });
+            return Some(0);
+        }
+
+        let (x, y) = lparam_point(lparam);
+        match msg {
+            WM_MOUSEMOVE => { self.on_mouse_move(x, y); }
+            WM_RBUTTONDOWN | WM_RBUTTONUP => { self.on_button(MouseButton::Right, msg==WM_RBUTTONDOWN, x, y); }
+            WM_MBUTTONDOWN | WM_MBUTTONUP => { self.on_button(MouseButton::Middle, msg==WM_MBUTTONDOWN, x, y); }
+            WM_XBUTTONDOWN | WM_XBUTTONUP => {
+                // The high word of wparam is XBUTTON1 (1) or XBUTTON2 (2).
+                let button = MouseButton::Extra(((wparam >> 16) & 0xffff) as u8);
+                self.on_button(button, msg==WM_XBUTTONDOWN, x, y);
+                return Some(1);
+            }
+            WM_MOUSEWHEEL | WM_MOUSEHWHEEL => {
+                let delta = (wparam >> 16) as i16 as i32;
+                self.on_wheel(msg==WM_MOUSEHWHEEL, delta, x, y);
+            }
+            WM_MOUSELEAVE => {
+                self.with_state_mut(|state: &mut MainFrameState| { state.mouse_inside = false; });
+                self.send_event(InputEvent::MouseLeave);
+            }
+            _ => { return None; }
+        }
+        Some(0)
     }
 }
 impl MainFrame {
-    fn new(instance: Instance, title: String, input_sink: Sender<InputEvent>, screen_source: Receiver<Screen>) -> Option<Window> {
+    fn new(instance: Instance, config: GridUiConfig, input_sink: Sender<InputEvent>, screen_source: Receiver<ScreenUpdate>,
+           palette_source: Receiver<Palette>, cursor_source: Receiver<Cursor>, grid_size: Arc<Mutex<(u32, u32)>>) -> Option<Window> {
         let wnd_class = WndClass {
             classname: "MainFrame".to_string(),
             style: 0x0001 | 0x0002, // CS_HREDRAW | CS_VREDRAW
@@ -253,28 +407,51 @@ impl MainFrame {
             return None;
         }
 
+        let style = if config.resizable {
+            window::WS_OVERLAPPEDWINDOW
+        } else {
+            window::WS_OVERLAPPEDWINDOW & !(WS_THICKFRAME | WS_MAXIMIZEBOX)
+        };
+        let atlas = config.atlas();
+        let cell_size = (atlas.part_width(), atlas.part_height());
+
         let wproc = Box::new(MainFrame {
             win: Window::null(),
-            font: RefCell::new(None),
+            atlas: atlas,
             input_sink: input_sink,
             screen_source: screen_source,
+            palette_source: palette_source,
+            cursor_source: cursor_source,
             state: RefCell::new(MainFrameState{
                 screen: Screen{
                     width:0,
                     glyphs: Vec::new()
                 },
+                palette: config.palette.clone(),
+                blink_visible: true,
+                blink_timer: false,
+                cursor: Cursor::hidden(),
+                cursor_blink_visible: true,
                 announced_grid_size: (-1,-1),  
+                high_surrogate: None,
+                mouse_inside: false,
+                wheel_remainder: (0, 0),
             }),
-            grid_height: 30,
+            grid_width: cell_size.0,
+            grid_height: cell_size.1,
+            grid_size: grid_size,
+            config: config.clone(),
+            style: style,
         });
 
+        let (width, height) = window_size_for_grid(style, config.clamp_grid_size(config.grid_size), cell_size);
         let win_params = WindowParams {
-            window_name: title,
-            style: window::WS_OVERLAPPEDWINDOW,
+            window_name: config.title.clone(),
+            style: style,
             x: 0,
             y: 0,
-            width: 400,
-            height: 400,
+            width: width,
+            height: height,
             parent: Window::null(),
             menu: ptr::null_mut(),
             ex_style: 0,
@@ -303,48 +480,214 @@ impl MainFrame {
     }
     
     fn check_for_new_screen(&self) {
-        let mut new_screen = None;
-        loop {
-            match self.screen_source.try_recv() {
-                Ok(screen) => {new_screen = Some(screen) },
-                Err(_) => { break; }
+        self.with_state_mut(|state: &mut MainFrameState| {
+            let mut damaged_cells = Vec::new();
+            let mut damaged_all = false;
+            while let Ok(update) = self.screen_source.try_recv() {
+                match state.screen.apply(update) {
+                    Damage::All => { damaged_all = true; }
+                    Damage::Cells(cells) => { damaged_cells.extend(cells); }
+                }
             }
-        }
-        
-        if let Some(s) = new_screen {
-            self.with_state_mut(|state: &mut MainFrameState| {
-                state.screen = s.clone();
+            let blinking = !state.screen.blinking_cells().is_empty();
+            if blinking && !state.blink_timer {
+                let interval = self.config.blink_interval.as_millis().max(1) as UINT;
+                unsafe { SetTimer(self.win.wnd, BLINK_TIMER as _, interval, None); }
+            } else if !blinking && state.blink_timer {
+                unsafe { KillTimer(self.win.wnd, BLINK_TIMER as _); }
+                // Start the next blinking text off visible, for a full interval.
+                state.blink_visible = true;
+            }
+            state.blink_timer = blinking;
+            while let Ok(palette) = self.palette_source.try_recv() {
+                state.palette = palette;
+                damaged_all = true;
+            }
+            while let Ok(cursor) = self.cursor_source.try_recv() {
+                damaged_cells.push((state.cursor.col, state.cursor.row));
+                state.cursor = cursor;
+                damaged_cells.push((cursor.col, cursor.row));
+                // Restarting the timer starts the cursor off visible, so it
+                // is seen at once where it went.
+                state.cursor_blink_visible = true;
+                match cursor.blink_interval {
+                    Some(interval) if cursor.visible => unsafe {
+                        SetTimer(self.win.wnd, CURSOR_TIMER as _, interval.as_millis().max(1) as UINT, None);
+                    },
+                    _ => unsafe { KillTimer(self.win.wnd, CURSOR_TIMER as _); },
+                }
+            }
+
+            if damaged_all {
                 self.win.invalidate(false);
-            });
-        }
+                return;
+            }
+
+            for (col, row) in damaged_cells {
+                self.invalidate_cell(col, row);
+            }
+        });
+    }
+
+    /// Shows or hides blinking glyphs, on every tick of the blink timer.
+    fn on_blink(&self) {
+        self.with_state_mut(|state: &mut MainFrameState| {
+            state.blink_visible = !state.blink_visible;
+            for (col, row) in state.screen.blinking_cells() {
+                self.invalidate_cell(col, row);
+            }
+        });
+    }
+
+    /// Shows or hides the cursor, on every tick of the cursor timer.
+    fn on_cursor_blink(&self) {
+        self.with_state_mut(|state: &mut MainFrameState| {
+            state.cursor_blink_visible = !state.cursor_blink_visible;
+            self.invalidate_cell(state.cursor.col, state.cursor.row);
+        });
+    }
+
+    fn invalidate_cell(&self, col: u32, row: u32) {
+        let rect = RECT {
+            left: (col * self.grid_width) as i32,
+            top: (row * self.grid_height) as i32,
+            right: ((col + 1) * self.grid_width) as i32,
+            bottom: ((row + 1) * self.grid_height) as i32,
+        };
+        unsafe { InvalidateRect(self.win.wnd, &rect, 0); }
     }
     
+    /// Keeps user resizes within the configured grid sizes.
+    fn on_get_min_max_info(&self, info: &mut MINMAXINFO) {
+        let style = self.style;
+        let (min_width, min_height) = window_size_for_grid(style, self.config.effective_min_grid_size(), (self.grid_width, self.grid_height));
+        info.ptMinTrackSize = POINT { x: min_width as i32, y: min_height as i32 };
+        let (max_width, max_height) = window_size_for_grid(style, self.config.effective_max_grid_size(), (self.grid_width, self.grid_height));
+        info.ptMaxTrackSize = POINT { x: max_width as i32, y: max_height as i32 };
+    }
+
+    fn mouse_position(&self, x: i32, y: i32) -> MousePosition {
+        MousePosition::from_pixels(x, y, self.grid_width, self.grid_height)
+    }
+
+    fn on_button(&self, button: MouseButton, down: bool, x: i32, y: i32) {
+        // Keep getting mouse messages while a button is held, so drags can
+        // leave the window.
+        unsafe {
+            if down { SetCapture(self.win.wnd); } else { ReleaseCapture(); }
+        }
+
+        let position = self.mouse_position(x, y);
+        self.send_event(if down {
+            InputEvent::MouseDown(button, position, current_modifiers())
+        } else {
+            InputEvent::MouseUp(button, position, current_modifiers())
+        });
+    }
+
+    fn on_mouse_move(&self, x: i32, y: i32) {
+        let position = self.mouse_position(x, y);
+
+        let mut entered = false;
+        self.with_state_mut(|state: &mut MainFrameState| {
+            entered = !state.mouse_inside;
+            state.mouse_inside = true;
+        });
+        if entered {
+            // Ask for a WM_MOUSELEAVE when the pointer leaves.
+            let mut track = TRACKMOUSEEVENT {
+                cbSize: ::std::mem::size_of::<TRACKMOUSEEVENT>() as DWORD,
+                dwFlags: 0x00000002, // TME_LEAVE
+                hwndTrack: self.win.wnd,
+                dwHoverTime: 0,
+            };
+            unsafe { TrackMouseEvent(&mut track); }
+            self.send_event(InputEvent::MouseEnter(position));
+        }
+
+        self.send_event(InputEvent::MouseMove(position, current_modifiers()));
+    }
+
+    /// Reports whole notches of wheel movement. `delta` is in 1/120ths of a
+    /// notch and (x, y) are screen coordinates.
+    fn on_wheel(&self, horizontal: bool, delta: i32, x: i32, y: i32) {
+        let mut notches = 0;
+        self.with_state_mut(|state: &mut MainFrameState| {
+            let remainder = if horizontal { &mut state.wheel_remainder.0 } else { &mut state.wheel_remainder.1 };
+            *remainder += delta;
+            notches = *remainder / WHEEL_DELTA;
+            *remainder %= WHEEL_DELTA;
+        });
+        if notches == 0 {
+            return;
+        }
+
+        let mut point = POINT { x: x, y: y };
+        unsafe { ScreenToClient(self.win.wnd, &mut point); }
+        let position = self.mouse_position(point.x, point.y);
+        let evt = if horizontal {
+            InputEvent::MouseWheel(notches, 0, position, current_modifiers())
+        } else {
+            InputEvent::MouseWheel(0, notches, position, current_modifiers())
+        };
+        self.send_event(evt);
+    }
+
+    /// Handles one UTF-16 code unit of typed text. Characters outside the
+    /// basic multilingual plane arrive as two WM_CHARs.
+    fn on_char(&self, unit: u16) {
+        let mut text = None;
+        self.with_state_mut(|state: &mut MainFrameState| {
+            let units = match state.high_surrogate.take() {
+                Some(high) => vec![high, unit],
+                None if 0xD800 <= unit && unit < 0xDC00 => {
+                    state.high_surrogate = Some(unit);
+                    return;
+                }
+                None => vec![unit],
+            };
+            text = Some(char::decode_utf16(units.into_iter())
+                .filter_map(|ch| ch.ok())
+                .filter(|ch| !ch.is_control())
+                .collect::<String>());
+        });
+
+        if let Some(text) = text {
+            if !text.is_empty() {
+                self.send_event(InputEvent::TextInput(text));
+            }
+        }
+    }
+
     fn send_event(&self, evt: InputEvent) {
         self.input_sink.send(evt).ok().expect("Window interface unexpectedly closed")
     }
 }
 
-pub trait GridUiInterface {
-    fn send_screen(&self, screen: Screen);
-    fn get_input_event(&self) -> InputEvent;
-}
-
 pub struct WindowsGridUi {
-    screen_sink: Sender<Screen>,
+    screen_sink: Sender<ScreenUpdate>,
+    palette_sink: Sender<Palette>,
+    cursor_sink: Sender<Cursor>,
     pub input_event_source: Receiver<InputEvent>,
     window: Window,
+    grid_size: Arc<Mutex<(u32, u32)>>,
 }
 
 impl WindowsGridUi {
-    pub fn new() -> WindowsGridUi {
+    pub fn new(config: GridUiConfig) -> WindowsGridUi {
         let (tx, rx) = channel();
         let (screen_tx, screen_rx) = channel();
+        let (palette_tx, palette_rx) = channel();
+        let (cursor_tx, cursor_rx) = channel();
         
 
+        let grid_size = Arc::new(Mutex::new((0, 0)));
+        let frame_grid_size = grid_size.clone();
+
         let (window_tx, window_rx) = channel();
         thread::spawn(move|| {
             let instance = Instance::main_instance();
-            let win = MainFrame::new(instance, "Grid UI".to_string(), tx, screen_rx).expect("Failed to create main window");
+            let win = MainFrame::new(instance, config, tx, screen_rx, palette_rx, cursor_rx, frame_grid_size).expect("Failed to create main window");
             win.show(1);
             win.update();
             
@@ -356,18 +699,45 @@ impl WindowsGridUi {
         WindowsGridUi {
             window: window_rx.recv().ok().expect("Failed to create window"),
             screen_sink: screen_tx,
+            palette_sink: palette_tx,
+            cursor_sink: cursor_tx,
             input_event_source: rx,
+            // Showing the window sent it a WM_SIZE, so this is already set.
+            grid_size: grid_size,
         }
     }
 }
 
 impl GridUiInterface for WindowsGridUi {
-    fn send_screen(&self, screen: Screen) {
-        self.screen_sink.send(screen).ok().expect("Could not send screen to window, which has unexpectedly closed");
+    fn send_update(&self, update: ScreenUpdate) {
+        self.screen_sink.send(update).ok();
+        self.window.post_message(WM_CHECK_SCREENS,0,0);
+    }
+
+    fn set_palette(&self, palette: Palette) {
+        self.palette_sink.send(palette).ok();
+        self.window.post_message(WM_CHECK_SCREENS,0,0);
+    }
+
+    fn set_cursor(&self, cursor: Cursor) {
+        self.cursor_sink.send(cursor).ok();
         self.window.post_message(WM_CHECK_SCREENS,0,0);
     }
+
+    fn set_title(&self, title: &str) {
+        let wide: Vec<u16> = title.encode_utf16().chain(Some(0)).collect();
+        unsafe { SetWindowTextW(self.window.wnd, wide.as_ptr()); }
+    }
     
     fn get_input_event(&self) -> InputEvent {
-        self.input_event_source.recv().ok().expect("GuidUiInterface failed to receive an input event")
+        self.input_event_source.recv().unwrap_or(InputEvent::Close)
+    }
+
+    fn try_get_input_event(&self) -> Option<InputEvent> {
+        self.input_event_source.try_recv().ok()
+    }
+
+    fn grid_size(&self) -> (u32, u32) {
+        *self.grid_size.lock().unwrap()
     }
 }
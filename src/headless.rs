@@ -0,0 +1,510 @@
+use std::cell::{Cell, Ref, RefCell};
+use std::sync::mpsc::{channel, Sender, Receiver};
+
+use atlas::GlyphAtlas;
+use color::Palette;
+use config::GridUiConfig;
+use input_event::InputEvent;
+use interface::GridUiInterface;
+use screen::{Attributes, Cursor, CursorShape, Damage, Glyph, Screen, ScreenUpdate};
+
+/// What parts of the framebuffer not covered by any cell are filled with. This
+/// matches the background of the X11 window.
+const EMPTY_PIXEL: [u8; 4] = [0xff, 0xff, 0xff, 0xff];
+
+/// An RGBA image, stored row by row with 4 bytes per pixel.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct Framebuffer {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Framebuffer {
+        let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+        for _ in 0..width*height {
+            pixels.extend_from_slice(&EMPTY_PIXEL);
+        }
+
+        Framebuffer {
+            width: width,
+            height: height,
+            pixels: pixels,
+        }
+    }
+
+    /// Wraps existing RGBA data. Panics if `pixels` is the wrong length.
+    pub fn from_rgba(width: u32, height: u32, pixels: Vec<u8>) -> Framebuffer {
+        assert_eq!(pixels.len(), (width * height * 4) as usize, "pixel data does not match dimensions");
+        Framebuffer {
+            width: width,
+            height: height,
+            pixels: pixels,
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels[..]
+    }
+
+    /// The RGBA value at (x, y). Panics if the point is outside the image.
+    pub fn pixel(&self, x: u32, y: u32) -> [u8; 4] {
+        assert!(x < self.width && y < self.height, "pixel ({}, {}) is outside the framebuffer", x, y);
+        let offset = ((y * self.width + x) * 4) as usize;
+        [self.pixels[offset], self.pixels[offset + 1], self.pixels[offset + 2], self.pixels[offset + 3]]
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, rgba: [u8; 4]) {
+        let offset = ((y * self.width + x) * 4) as usize;
+        self.pixels[offset..offset + 4].copy_from_slice(&rgba);
+    }
+
+    /// Sets a rectangle back to the color of pixels no cell covers.
+    pub fn clear(&mut self, left: u32, top: u32, width: u32, height: u32) {
+        for y in top..top + height {
+            for x in left..left + width {
+                self.set_pixel(x, y, EMPTY_PIXEL);
+            }
+        }
+    }
+}
+
+/// Converts 0x00RRGGBB to RGBA.
+fn color_to_rgba(color: u32) -> [u8; 4] {
+    [(color >> 16) as u8, (color >> 8) as u8, color as u8, 0xff]
+}
+
+/// Draws one glyph with its top left corner at (left, top): the background,
+/// then each of its parts in order, overlaid in the foreground color, then
+/// any lines. Indexed colors are looked up in `palette`. Blinking glyphs are
+/// drawn visible; see `Glyph::as_shown`.
+pub fn draw_glyph(atlas: &GlyphAtlas, palette: &Palette, framebuffer: &mut Framebuffer, glyph: &Glyph, left: u32, top: u32) {
+    let (foreground, background) = glyph.colors();
+    let foreground = color_to_rgba(palette.rgb(foreground));
+    let background = color_to_rgba(palette.rgb(background));
+    let (width, height) = (atlas.part_width(), atlas.part_height());
+
+    for y in 0..height {
+        for x in 0..width {
+            framebuffer.set_pixel(left + x, top + y, background);
+        }
+    }
+
+    let attributes = glyph.attributes;
+    let bold_offset = if attributes.contains(Attributes::BOLD) { atlas.bold_offset() } else { 0 };
+    for part in atlas.parts(glyph.character) {
+        for y in 0..height {
+            let shift = if attributes.contains(Attributes::ITALIC) { atlas.italic_shift(y) } else { 0 };
+            for x in 0..width {
+                if atlas.is_set(part, x, y) {
+                    // Bold fills the gap between the two copies, so strokes
+                    // thicken rather than double.
+                    for dx in 0..bold_offset + 1 {
+                        if x + shift + dx < width {
+                            framebuffer.set_pixel(left + x + shift + dx, top + y, foreground);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    for rows in atlas.lines(attributes) {
+        for y in rows {
+            for x in 0..width {
+                framebuffer.set_pixel(left + x, top + y, foreground);
+            }
+        }
+    }
+}
+
+/// Draws the line of an underline or bar cursor over `glyph`, already drawn
+/// at (left, top), in its foreground color. A block cursor has no line; the
+/// glyph is drawn inverted instead, as `Cursor::over` gives it.
+pub fn draw_cursor(atlas: &GlyphAtlas, palette: &Palette, framebuffer: &mut Framebuffer, shape: CursorShape, glyph: &Glyph,
+                   left: u32, top: u32) {
+    if let Some((columns, rows)) = atlas.cursor_rect(shape) {
+        let foreground = color_to_rgba(palette.rgb(glyph.colors().0));
+        for y in rows {
+            for x in columns.clone() {
+                framebuffer.set_pixel(left + x, top + y, foreground);
+            }
+        }
+    }
+}
+
+/// Rasterizes `screen` into a framebuffer just large enough to hold it. A
+/// partially filled last row leaves the rest of that row empty.
+pub fn render_screen(atlas: &GlyphAtlas, palette: &Palette, screen: &Screen) -> Framebuffer {
+    if screen.width == 0 {
+        return Framebuffer::new(0, 0);
+    }
+
+    let mut framebuffer = Framebuffer::new(screen.width * atlas.part_width(), screen.height() * atlas.part_height());
+    for (idx, glyph) in screen.glyphs.iter().enumerate() {
+        let col = idx as u32 % screen.width;
+        let row = idx as u32 / screen.width;
+        draw_glyph(atlas, palette, &mut framebuffer, glyph, col * atlas.part_width(), row * atlas.part_height());
+    }
+    framebuffer
+}
+
+/// A grid UI that draws into memory instead of a window, for use where there
+/// is no display.
+///
+/// Input events never arrive on their own; they can be injected with
+/// `send_input_event`. `get_input_event` blocks forever if none are queued.
+pub struct HeadlessGridUi {
+    config: GridUiConfig,
+    atlas: GlyphAtlas,
+    palette: RefCell<Palette>,
+    screen: RefCell<Screen>,
+    framebuffer: RefCell<Framebuffer>,
+    /// Whether blinking glyphs are in their visible phase.
+    blink_visible: Cell<bool>,
+    cursor: Cell<Cursor>,
+    /// Whether the cursor is in the visible phase of its blinking.
+    cursor_blink_visible: Cell<bool>,
+    title: RefCell<String>,
+    grid_size: Cell<(u32, u32)>,
+    input_event_sink: Sender<InputEvent>,
+    pub input_event_source: Receiver<InputEvent>,
+}
+
+impl HeadlessGridUi {
+    pub fn new(config: GridUiConfig) -> HeadlessGridUi {
+        let (input_event_sink, input_event_source) = channel();
+
+        HeadlessGridUi {
+            atlas: config.atlas(),
+            palette: RefCell::new(config.palette.clone()),
+            screen: RefCell::new(Screen{ glyphs: Vec::new(), width: 0 }),
+            framebuffer: RefCell::new(Framebuffer::new(0, 0)),
+            blink_visible: Cell::new(true),
+            cursor: Cell::new(Cursor::hidden()),
+            cursor_blink_visible: Cell::new(true),
+            title: RefCell::new(config.title.clone()),
+            grid_size: Cell::new(config.clamp_grid_size(config.grid_size)),
+            config: config,
+            input_event_sink: input_event_sink,
+            input_event_source: input_event_source,
+        }
+    }
+
+    /// The image of the screen as of the most recent update.
+    pub fn framebuffer(&self) -> Ref<'_, Framebuffer> {
+        self.framebuffer.borrow()
+    }
+
+    pub fn title(&self) -> String {
+        self.title.borrow().clone()
+    }
+
+    /// Changes the grid size, and queues an `InputEvent::Size` if it differs,
+    /// as if the user had resized the window. The size is limited to what the
+    /// config allows.
+    pub fn resize(&self, cols: u32, rows: u32) {
+        let size = self.config.clamp_grid_size((cols, rows));
+        if self.grid_size.replace(size) != size {
+            self.send_input_event(InputEvent::Size(size.0, size.1));
+        }
+    }
+
+    /// Moves blinking glyphs to their next phase, as a native backend's timer
+    /// does every `GridUiConfig::blink_interval`.
+    pub fn blink(&self) {
+        self.blink_visible.set(!self.blink_visible.get());
+        let screen = self.screen.borrow();
+        self.draw_cells(&screen, &screen.blinking_cells());
+    }
+
+    pub fn cursor(&self) -> Cursor {
+        self.cursor.get()
+    }
+
+    /// Moves the cursor to the next phase of its blinking, as a native
+    /// backend's timer does every `Cursor::blink_interval`.
+    pub fn blink_cursor(&self) {
+        self.cursor_blink_visible.set(!self.cursor_blink_visible.get());
+        self.draw_cursor_cell(&self.screen.borrow(), self.cursor.get());
+    }
+
+    /// The cursor, if it is drawn right now.
+    fn shown_cursor(&self) -> Option<Cursor> {
+        let cursor = self.cursor.get();
+        if cursor.visible && self.cursor_blink_visible.get() { Some(cursor) } else { None }
+    }
+
+    fn draw_cursor_cell(&self, screen: &Screen, cursor: Cursor) {
+        if screen.get(cursor.col, cursor.row).is_some() {
+            self.draw_cells(screen, &[(cursor.col, cursor.row)]);
+        }
+    }
+
+    fn draw_all(&self, screen: &Screen) {
+        let shown = Screen {
+            glyphs: screen.glyphs.iter().map(|glyph| glyph.as_shown(self.blink_visible.get())).collect(),
+            width: screen.width,
+        };
+        *self.framebuffer.borrow_mut() = render_screen(&self.atlas, &self.palette.borrow(), &shown);
+        self.draw_cursor_cell(screen, self.cursor.get());
+    }
+
+    fn draw_cells(&self, screen: &Screen, cells: &[(u32, u32)]) {
+        let palette = self.palette.borrow();
+        let mut framebuffer = self.framebuffer.borrow_mut();
+        let cursor = self.shown_cursor();
+        for &(col, row) in cells {
+            let (left, top) = (col * self.atlas.part_width(), row * self.atlas.part_height());
+            let glyph = screen.glyphs[(row * screen.width + col) as usize].as_shown(self.blink_visible.get());
+            match cursor {
+                Some(cursor) if (cursor.col, cursor.row) == (col, row) => {
+                    let glyph = cursor.over(glyph);
+                    draw_glyph(&self.atlas, &palette, &mut framebuffer, &glyph, left, top);
+                    draw_cursor(&self.atlas, &palette, &mut framebuffer, cursor.shape, &glyph, left, top);
+                }
+                _ => { draw_glyph(&self.atlas, &palette, &mut framebuffer, &glyph, left, top); }
+            }
+        }
+    }
+
+    /// Queues an event to be read from `input_event_source`, as if the user had
+    /// caused it.
+    pub fn send_input_event(&self, evt: InputEvent) {
+        self.input_event_sink.send(evt).expect("input_event_source was dropped");
+    }
+}
+
+impl GridUiInterface for HeadlessGridUi {
+    fn send_update(&self, update: ScreenUpdate) {
+        let mut screen = self.screen.borrow_mut();
+        match screen.apply(update) {
+            Damage::All => { self.draw_all(&screen); }
+            Damage::Cells(cells) => { self.draw_cells(&screen, &cells); }
+        }
+    }
+
+    fn set_palette(&self, palette: Palette) {
+        *self.palette.borrow_mut() = palette;
+        self.draw_all(&self.screen.borrow());
+    }
+
+    fn set_cursor(&self, cursor: Cursor) {
+        let old = self.cursor.replace(cursor);
+        // Like a native backend, restart the blinking so a moved cursor is
+        // seen at once.
+        self.cursor_blink_visible.set(true);
+        let screen = self.screen.borrow();
+        self.draw_cursor_cell(&screen, old);
+        self.draw_cursor_cell(&screen, cursor);
+    }
+
+    fn set_title(&self, title: &str) {
+        *self.title.borrow_mut() = title.to_string();
+    }
+
+    fn get_input_event(&self) -> InputEvent {
+        self.input_event_source.recv().expect("input_event_sink was dropped")
+    }
+
+    fn try_get_input_event(&self) -> Option<InputEvent> {
+        self.input_event_source.try_recv().ok()
+    }
+
+    fn grid_size(&self) -> (u32, u32) {
+        self.grid_size.get()
+    }
+}
+
+impl Default for HeadlessGridUi {
+    fn default() -> HeadlessGridUi {
+        HeadlessGridUi::new(GridUiConfig::default())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{render_screen, HeadlessGridUi};
+    use atlas::GlyphAtlas;
+    use color::{Color, Palette};
+    use input_event::{InputEvent, Key, Modifiers};
+    use interface::GridUiInterface;
+    use config::GridUiConfig;
+    use glyph_parts::{MissingCharacter, PartTable};
+    use screen::{Attributes, Cursor, CursorShape, Glyph, Screen, ScreenUpdate};
+
+    fn two_digits() -> Screen {
+        Screen {
+            glyphs: vec![
+                Glyph{ character: 11, foreground: Color::Rgb(0xff0000), background: Color::Rgb(0x00ff00), attributes: Attributes::NONE },
+                Glyph{ character: 10, foreground: Color::Rgb(0x000000), background: Color::Rgb(0x0000ff), attributes: Attributes::NONE },
+                Glyph{ character: 0, foreground: Color::Rgb(0x000000), background: Color::Rgb(0x123456), attributes: Attributes::NONE },
+            ],
+            width: 2,
+        }
+    }
+
+    #[test]
+    fn dimensions() {
+        let framebuffer = render_screen(&GlyphAtlas::builtin(), &Palette::default(), &two_digits());
+        assert_eq!(framebuffer.width(), 40);
+        assert_eq!(framebuffer.height(), 80);
+        assert_eq!(framebuffer.pixels().len(), 40*80*4);
+    }
+
+    #[test]
+    fn partial_row() {
+        let gridui = HeadlessGridUi::default();
+        gridui.send_screen(two_digits());
+        assert_eq!(gridui.framebuffer().pixel(30, 60), [0xff, 0xff, 0xff, 0xff]);
+
+        let mut changed = two_digits();
+        changed.glyphs[2].background = Color::Rgb(0xabcdef);
+        gridui.send_update(ScreenUpdate::between(&two_digits(), &changed));
+        assert_eq!(gridui.framebuffer().pixel(10, 60), [0xab, 0xcd, 0xef, 0xff]);
+    }
+
+    #[test]
+    fn colors() {
+        let framebuffer = render_screen(&GlyphAtlas::builtin(), &Palette::default(), &two_digits());
+
+        // The stem of the '1' is foreground, its corner is background.
+        assert_eq!(framebuffer.pixel(10, 20), [0xff, 0x00, 0x00, 0xff]);
+        assert_eq!(framebuffer.pixel(0, 0), [0x00, 0xff, 0x00, 0xff]);
+        assert_eq!(framebuffer.pixel(20, 0), [0x00, 0x00, 0xff, 0xff]);
+
+        // The space, and the empty half of the last row.
+        assert_eq!(framebuffer.pixel(10, 60), [0x12, 0x34, 0x56, 0xff]);
+        assert_eq!(framebuffer.pixel(30, 60), [0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn injected_input() {
+        let gridui = HeadlessGridUi::default();
+        gridui.send_input_event(InputEvent::KeyDown(Key::Character(0x1000), Modifiers::NONE));
+        match gridui.input_event_source.try_recv() {
+            Ok(InputEvent::KeyDown(Key::Character(0x1000), Modifiers::NONE)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn partial_update() {
+        let gridui = HeadlessGridUi::default();
+        gridui.send_screen(two_digits());
+
+        let mut changed = two_digits();
+        changed.glyphs[1] = Glyph{ character: 12, foreground: Color::Rgb(0xffffff), background: Color::Rgb(0x000000), attributes: Attributes::NONE };
+        gridui.send_update(ScreenUpdate::between(&two_digits(), &changed));
+        assert_eq!(*gridui.framebuffer(), render_screen(&GlyphAtlas::builtin(), &Palette::default(), &changed));
+    }
+
+    #[test]
+    fn resize() {
+        let gridui = HeadlessGridUi::new(GridUiConfig::new().grid_size(80, 24).max_grid_size(100, 100));
+        gridui.resize(80, 24);
+        assert!(gridui.try_get_input_event().is_none());
+
+        gridui.resize(120, 30);
+        assert_eq!(gridui.grid_size(), (100, 30));
+        match gridui.try_get_input_event() {
+            Some(InputEvent::Size(100, 30)) => {},
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn config() {
+        let gridui = HeadlessGridUi::new(GridUiConfig::new().title("Inventory").scale(2));
+        assert_eq!(gridui.title(), "Inventory");
+        gridui.set_title("Orders");
+        assert_eq!(gridui.title(), "Orders");
+
+        gridui.send_screen(two_digits());
+        assert_eq!(gridui.framebuffer().width(), 80);
+    }
+
+    #[test]
+    fn font() {
+        // One solid 8x16 part, drawn for '1'.
+        let mut table = PartTable::new(MissingCharacter::Frame(1));
+        table.insert(11, vec![0]);
+        let mut bits = vec![0xff; 16];
+        bits.extend(vec![0; 16]);
+        let font = GlyphAtlas::new(8, 16, bits, table);
+
+        let gridui = HeadlessGridUi::new(GridUiConfig::new().font(font));
+        gridui.send_screen(two_digits());
+        let framebuffer = gridui.framebuffer();
+        assert_eq!((framebuffer.width(), framebuffer.height()), (16, 32));
+        assert_eq!(framebuffer.pixel(7, 15), [0xff, 0x00, 0x00, 0xff]);
+        assert_eq!(framebuffer.pixel(8, 0), [0x00, 0x00, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn attributes() {
+        let glyph = Glyph{ character: 11, foreground: Color::Rgb(0xff0000), background: Color::Rgb(0x00ff00),
+                           attributes: Attributes::UNDERLINE | Attributes::INVERSE | Attributes::BLINK };
+        let gridui = HeadlessGridUi::default();
+        gridui.send_screen(Screen::new(1, 1, glyph));
+        assert_eq!(gridui.framebuffer().pixel(0, 0), [0xff, 0x00, 0x00, 0xff]);
+        assert_eq!(gridui.framebuffer().pixel(0, 33), [0x00, 0xff, 0x00, 0xff]);
+        assert_eq!(gridui.framebuffer().pixel(10, 20), [0x00, 0xff, 0x00, 0xff]);
+
+        // Hidden, it is all background.
+        gridui.blink();
+        assert_eq!(gridui.framebuffer().pixel(0, 33), [0xff, 0x00, 0x00, 0xff]);
+        assert_eq!(gridui.framebuffer().pixel(10, 20), [0xff, 0x00, 0x00, 0xff]);
+        gridui.blink();
+        assert_eq!(gridui.framebuffer().pixel(10, 20), [0x00, 0xff, 0x00, 0xff]);
+    }
+
+    #[test]
+    fn cursor() {
+        let glyph = Glyph{ character: 0, foreground: Color::Rgb(0xff0000), background: Color::Rgb(0x00ff00), attributes: Attributes::NONE };
+        let gridui = HeadlessGridUi::default();
+        gridui.send_screen(Screen::new(2, 1, glyph));
+        assert_eq!(gridui.framebuffer().pixel(30, 20), [0x00, 0xff, 0x00, 0xff]);
+
+        // A block inverts its cell, and only while in the visible phase.
+        gridui.set_cursor(Cursor::new(1, 0));
+        assert_eq!(gridui.framebuffer().pixel(30, 20), [0xff, 0x00, 0x00, 0xff]);
+        assert_eq!(gridui.framebuffer().pixel(10, 20), [0x00, 0xff, 0x00, 0xff]);
+        gridui.blink_cursor();
+        assert_eq!(gridui.framebuffer().pixel(30, 20), [0x00, 0xff, 0x00, 0xff]);
+
+        // Setting it again starts it off visible, and clears the old cell.
+        gridui.set_cursor(Cursor::new(0, 0).shape(CursorShape::Bar));
+        assert_eq!(gridui.framebuffer().pixel(0, 20), [0xff, 0x00, 0x00, 0xff]);
+        assert_eq!(gridui.framebuffer().pixel(10, 20), [0x00, 0xff, 0x00, 0xff]);
+
+        // It survives redrawing the screen under it.
+        gridui.send_screen(Screen::new(2, 1, glyph));
+        assert_eq!(gridui.framebuffer().pixel(0, 20), [0xff, 0x00, 0x00, 0xff]);
+
+        gridui.set_cursor(Cursor::hidden());
+        assert_eq!(gridui.framebuffer().pixel(0, 20), [0x00, 0xff, 0x00, 0xff]);
+        // Off the screen, it is ignored.
+        gridui.set_cursor(Cursor::new(5, 5));
+    }
+
+    #[test]
+    fn palette() {
+        let gridui = HeadlessGridUi::new(GridUiConfig::new().palette(Palette::ansi()));
+        let glyph = Glyph{ character: 0, foreground: Color::BLACK, background: Color::Indexed(1), attributes: Attributes::NONE };
+        gridui.send_screen(Screen::new(1, 1, glyph));
+        assert_eq!(gridui.framebuffer().pixel(0, 0), [0xaa, 0x00, 0x00, 0xff]);
+
+        gridui.set_palette(Palette::new(vec![0, 0x123456]));
+        assert_eq!(gridui.framebuffer().pixel(0, 0), [0x12, 0x34, 0x56, 0xff]);
+    }
+}
@@ -1,9 +1,147 @@
-#[derive(Debug, Copy, Clone)]
+use std::ops::BitOr;
+
+use glyphcode::GlyphCode;
+
+/// A key on the keyboard.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Key {
+    /// A key that types a glyph, identified by that glyph. Letter keys are
+    /// always the lowercase letter.
+    Character(GlyphCode),
+    Up,
+    Down,
+    Left,
+    Right,
+    Enter,
+    Escape,
+    Tab,
+    Backspace,
+    Delete,
+    Home,
+    End,
+    PageUp,
+    PageDown,
+    /// F1 through F12, numbered from 1.
+    Function(u8),
+}
+
+/// The set of modifier keys held down when an event happened.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Modifiers(u8);
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers(0);
+    pub const SHIFT: Modifiers = Modifiers(1);
+    pub const CONTROL: Modifiers = Modifiers(2);
+    pub const ALT: Modifiers = Modifiers(4);
+    /// The Windows or Command key.
+    pub const SUPER: Modifiers = Modifiers(8);
+
+    /// Whether every modifier in `other` is held.
+    pub fn contains(self, other: Modifiers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn shift(self) -> bool { self.contains(Modifiers::SHIFT) }
+    pub fn control(self) -> bool { self.contains(Modifiers::CONTROL) }
+    pub fn alt(self) -> bool { self.contains(Modifiers::ALT) }
+    pub fn super_key(self) -> bool { self.contains(Modifiers::SUPER) }
+}
+
+impl BitOr for Modifiers {
+    type Output = Modifiers;
+
+    fn bitor(self, other: Modifiers) -> Modifiers {
+        Modifiers(self.0 | other.0)
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MouseButton {
+    Left,
+    Middle,
+    Right,
+    /// Side buttons, numbered from 1. Button 1 is usually "back" and 2
+    /// "forward": X11 buttons 8 and 9, or XBUTTON1 and XBUTTON2 on Windows.
+    /// Further X11 buttons continue from 3.
+    Extra(u8),
+}
+
+/// Where the mouse pointer is, as a cell and a pixel within that cell.
+///
+/// While a button is held the pointer can be dragged outside the window, so
+/// cells can be negative or past the edge of the grid.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct MousePosition {
+    pub col: i32,
+    pub row: i32,
+    /// Pixels from the left edge of the cell.
+    pub x_offset: u32,
+    /// Pixels from the top edge of the cell.
+    pub y_offset: u32,
+}
+
+impl MousePosition {
+    /// The position of the pixel at (x, y) in the window, given the size of a
+    /// cell.
+    pub fn from_pixels(x: i32, y: i32, cell_width: u32, cell_height: u32) -> MousePosition {
+        let (cell_width, cell_height) = (cell_width as i32, cell_height as i32);
+        MousePosition {
+            col: x.div_euclid(cell_width),
+            row: y.div_euclid(cell_height),
+            x_offset: x.rem_euclid(cell_width) as u32,
+            y_offset: y.rem_euclid(cell_height) as u32,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum InputEvent {
     Close,
-    MouseDown(u32, u32),
-    MouseUp(u32, u32),
-    KeyDown(u32),
-    KeyUp(u32),
+    MouseDown(MouseButton, MousePosition, Modifiers),
+    MouseUp(MouseButton, MousePosition, Modifiers),
+    MouseMove(MousePosition, Modifiers),
+    /// The wheel turned by (horizontal, vertical) notches. Positive values
+    /// scroll right and up, away from the user.
+    MouseWheel(i32, i32, MousePosition, Modifiers),
+    /// The pointer moved into the window.
+    MouseEnter(MousePosition),
+    /// The pointer moved out of the window.
+    MouseLeave,
+    KeyDown(Key, Modifiers),
+    KeyUp(Key, Modifiers),
+    /// Text the user typed, after the keyboard layout, dead keys and input
+    /// methods have been applied. `glyphcode::from_str` converts it to glyphs.
+    /// Keys like Enter and Backspace produce only `KeyDown`, not text.
+    TextInput(String),
     Size(u32, u32),
 }
+
+#[cfg(test)]
+mod test {
+    use super::{Modifiers, MousePosition};
+
+    #[test]
+    fn modifier_sets() {
+        let ctrl_shift = Modifiers::CONTROL | Modifiers::SHIFT;
+        assert!(ctrl_shift.control() && ctrl_shift.shift());
+        assert!(!ctrl_shift.alt() && !ctrl_shift.super_key());
+        assert!(ctrl_shift.contains(Modifiers::SHIFT));
+        assert!(!Modifiers::SHIFT.contains(ctrl_shift));
+        assert!(Modifiers::NONE.is_empty());
+        assert_eq!(Modifiers::default(), Modifiers::NONE);
+    }
+
+    #[test]
+    fn mouse_positions() {
+        let inside = MousePosition::from_pixels(45, 39, 20, 40);
+        assert_eq!(inside, MousePosition{ col: 2, row: 0, x_offset: 5, y_offset: 39 });
+
+        let dragged_out = MousePosition::from_pixels(-1, -41, 20, 40);
+        assert_eq!(dragged_out, MousePosition{ col: -1, row: -2, x_offset: 19, y_offset: 39 });
+    }
+}
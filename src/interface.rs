@@ -0,0 +1,94 @@
+use color::Palette;
+use input_event::InputEvent;
+use screen::{Cursor, Screen, ScreenUpdate};
+use config::GridUiConfig;
+
+/// What every grid UI backend provides: a way to show a `Screen` and a stream
+/// of the user's input.
+///
+/// Applications written against this trait, usually as a
+/// `Box<dyn GridUiInterface>`, can run on any backend, including
+/// `HeadlessGridUi` in tests.
+///
+/// When the user closes the window, `InputEvent::Close` is sent. After that,
+/// changes to what is displayed are ignored and `get_input_event` returns
+/// `Close` again rather than waiting forever.
+pub trait GridUiInterface {
+    /// Changes what is displayed. Only the cells the update touches are
+    /// repainted.
+    fn send_update(&self, update: ScreenUpdate);
+
+    /// Replaces what is displayed with `screen`.
+    fn send_screen(&self, screen: Screen) {
+        self.send_update(ScreenUpdate::Full(screen));
+    }
+
+    /// Replaces the colors `Color::Indexed` refers to, and redraws every
+    /// glyph in the new colors.
+    fn set_palette(&self, palette: Palette);
+
+    /// Moves, reshapes, shows or hides the cursor. The backend draws it and
+    /// blinks it by itself; a cursor outside the screen isn't drawn.
+    fn set_cursor(&self, cursor: Cursor);
+
+    /// Changes the window title.
+    fn set_title(&self, title: &str);
+
+    /// Waits for the next input event.
+    fn get_input_event(&self) -> InputEvent;
+
+    /// Returns the next input event if one is already waiting.
+    fn try_get_input_event(&self) -> Option<InputEvent>;
+
+    /// The number of (columns, rows) that currently fit. Changes are also
+    /// reported as `InputEvent::Size`.
+    fn grid_size(&self) -> (u32, u32);
+}
+
+/// Opens a window using the native backend for this platform. The backend
+/// types themselves differ by platform, so portable applications open
+/// windows through this and use them through `GridUiInterface`.
+#[cfg(target_os="linux")]
+pub fn open_native(config: GridUiConfig) -> Box<dyn GridUiInterface> {
+    Box::new(::x11::GridUi::new(config))
+}
+
+/// Opens a window using the native backend for this platform. The backend
+/// types themselves differ by platform, so portable applications open
+/// windows through this and use them through `GridUiInterface`.
+#[cfg(target_os="windows")]
+pub fn open_native(config: GridUiConfig) -> Box<dyn GridUiInterface> {
+    Box::new(::gridui::WindowsGridUi::new(config))
+}
+
+#[cfg(test)]
+mod test {
+    use super::GridUiInterface;
+    use color::Color;
+    use headless::HeadlessGridUi;
+    use input_event::{InputEvent, Key, Modifiers};
+    use screen::{Attributes, Glyph, Screen};
+
+    /// Stands in for application code, which only sees the trait.
+    fn echo_key(ui: &dyn GridUiInterface) {
+        if let InputEvent::KeyDown(Key::Character(character), _) = ui.get_input_event() {
+            ui.send_screen(Screen{
+                glyphs: vec![Glyph{ character: character, foreground: Color::BLACK, background: Color::WHITE, attributes: Attributes::NONE }],
+                width: 1,
+            });
+        }
+    }
+
+    #[test]
+    fn backend_behind_trait() {
+        let headless = HeadlessGridUi::default();
+        headless.send_input_event(InputEvent::KeyDown(Key::Character(11), Modifiers::SHIFT));
+        echo_key(&headless);
+        assert_eq!(headless.framebuffer().width(), 20);
+        assert!(headless.try_get_input_event().is_none());
+
+        let boxed: Box<dyn GridUiInterface> = Box::new(headless);
+        assert!(boxed.try_get_input_event().is_none());
+        assert_eq!(boxed.grid_size(), (32, 12));
+    }
+}
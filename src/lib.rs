@@ -30,14 +30,21 @@ pub mod gridui;
 #[cfg(target_os="linux")]
 pub mod x11;
 
-#[cfg(target_os="linux")]
-pub use x11 as gridui;
-
 #[cfg(target_os="linux")]
 extern crate x11_dl;
 
 pub mod glyphcode;
 
 pub mod screen;
+pub mod color;
+pub mod text;
 pub mod input_event;
 pub mod glyph_parts;
+pub mod interface;
+pub mod config;
+pub mod atlas;
+pub mod font;
+pub mod headless;
+pub mod snapshot;
+
+pub use interface::{GridUiInterface, open_native};
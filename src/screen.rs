@@ -1,13 +1,569 @@
+use std::ops::{BitOr, Range};
+use std::time::Duration;
 
-#[derive(Copy, Clone, Debug)]
+use color::Color;
+
+/// How a glyph is drawn, besides its character and colors.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Default)]
+pub struct Attributes(u8);
+
+impl Attributes {
+    pub const NONE: Attributes = Attributes(0);
+    /// Drawn a second time, slightly to the right.
+    pub const BOLD: Attributes = Attributes(1);
+    pub const UNDERLINE: Attributes = Attributes(2);
+    /// Slanted by shifting rows right, more towards the top.
+    pub const ITALIC: Attributes = Attributes(4);
+    pub const STRIKETHROUGH: Attributes = Attributes(8);
+    /// Foreground and background swapped.
+    pub const INVERSE: Attributes = Attributes(16);
+    /// Shown and hidden in turn, on a timer kept by the backend.
+    pub const BLINK: Attributes = Attributes(32);
+
+    /// Whether every attribute in `other` is set.
+    pub fn contains(self, other: Attributes) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+impl BitOr for Attributes {
+    type Output = Attributes;
+
+    fn bitor(self, other: Attributes) -> Attributes {
+        Attributes(self.0 | other.0)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Glyph {
     pub character: u32,
-    pub background: u32,
-    pub foreground: u32,
+    pub background: Color,
+    pub foreground: Color,
+    pub attributes: Attributes,
+}
+
+impl Glyph {
+    /// The (foreground, background) the glyph is drawn in, swapped if it is
+    /// `INVERSE`.
+    pub fn colors(&self) -> (Color, Color) {
+        if self.attributes.contains(Attributes::INVERSE) {
+            (self.background, self.foreground)
+        } else {
+            (self.foreground, self.background)
+        }
+    }
+
+    /// The glyph with its foreground and background swapped.
+    pub fn inverted(&self) -> Glyph {
+        Glyph { attributes: Attributes(self.attributes.0 ^ Attributes::INVERSE.0), ..*self }
+    }
+
+    /// The glyph as drawn in one phase of blinking. While blinking text is
+    /// hidden, a blinking glyph shows only its background.
+    pub fn as_shown(&self, blink_visible: bool) -> Glyph {
+        if blink_visible || !self.attributes.contains(Attributes::BLINK) {
+            return *self;
+        }
+        let background = self.colors().1;
+        Glyph { character: 0, foreground: background, background: background, attributes: Attributes::NONE }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum CursorShape {
+    /// The whole cell, drawn inverted.
+    Block,
+    /// A line along the bottom of the cell.
+    Underline,
+    /// A line up the left side of the cell.
+    Bar,
+}
+
+/// The text cursor, set with `GridUiInterface::set_cursor`. The backend
+/// draws it over the screen and blinks it on its own timer, so the screen
+/// needn't be resent.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Cursor {
+    pub col: u32,
+    pub row: u32,
+    pub visible: bool,
+    pub shape: CursorShape,
+    /// How long the cursor stays shown, then hidden. None for a cursor that
+    /// doesn't blink.
+    pub blink_interval: Option<Duration>,
+}
+
+impl Cursor {
+    /// A visible, blinking block cursor at (col, row).
+    pub fn new(col: u32, row: u32) -> Cursor {
+        Cursor {
+            col: col,
+            row: row,
+            visible: true,
+            shape: CursorShape::Block,
+            blink_interval: Some(Duration::from_millis(500)),
+        }
+    }
+
+    /// No cursor at all. This is what backends start with.
+    pub fn hidden() -> Cursor {
+        Cursor { visible: false, ..Cursor::new(0, 0) }
+    }
+
+    pub fn shape(mut self, shape: CursorShape) -> Cursor {
+        self.shape = shape;
+        self
+    }
+
+    /// Panics if `interval` is zero.
+    pub fn blink_interval(mut self, interval: Option<Duration>) -> Cursor {
+        assert!(interval != Some(Duration::from_millis(0)), "blink interval must not be zero");
+        self.blink_interval = interval;
+        self
+    }
+
+    /// `glyph` as drawn under the cursor: inverted by a block, unchanged by
+    /// other shapes, which are drawn over it.
+    pub fn over(&self, glyph: Glyph) -> Glyph {
+        match self.shape {
+            CursorShape::Block => glyph.inverted(),
+            CursorShape::Underline | CursorShape::Bar => glyph,
+        }
+    }
 }
 
-#[derive(Clone)]
+impl Default for Cursor {
+    fn default() -> Cursor {
+        Cursor::hidden()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Screen {
     pub glyphs: Vec<Glyph>,
     pub width: u32,
 }
+
+/// A rectangle of cells.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub col: u32,
+    pub row: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    pub fn new(col: u32, row: u32, width: u32, height: u32) -> Rect {
+        Rect { col: col, row: row, width: width, height: height }
+    }
+
+    /// The part of this rectangle that lies within `width` by `height` cells
+    /// at the origin.
+    fn clipped_to(&self, width: u32, height: u32) -> Rect {
+        let col = self.col.min(width);
+        let row = self.row.min(height);
+        Rect {
+            col: col,
+            row: row,
+            width: self.width.min(width - col),
+            height: self.height.min(height - row),
+        }
+    }
+}
+
+/// A new glyph for the cell at (col, row).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct CellUpdate {
+    pub col: u32,
+    pub row: u32,
+    pub glyph: Glyph,
+}
+
+/// A change to what is displayed, sent with `GridUiInterface::send_update`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScreenUpdate {
+    /// Replaces the whole screen.
+    Full(Screen),
+    /// Replaces some cells, leaving the rest as they were. Cells outside the
+    /// current screen are ignored.
+    Cells(Vec<CellUpdate>),
+}
+
+/// Which cells an update changed and so need repainting.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Damage {
+    All,
+    /// (col, row) of each changed cell.
+    Cells(Vec<(u32, u32)>),
+}
+
+impl ScreenUpdate {
+    /// The update that turns `old` into `new`. Only differing cells are sent
+    /// unless the dimensions changed.
+    pub fn between(old: &Screen, new: &Screen) -> ScreenUpdate {
+        if old.width != new.width || old.glyphs.len() != new.glyphs.len() || new.width == 0 {
+            return ScreenUpdate::Full(new.clone());
+        }
+
+        let mut cells = Vec::new();
+        for (idx, (old_glyph, new_glyph)) in old.glyphs.iter().zip(new.glyphs.iter()).enumerate() {
+            if old_glyph != new_glyph {
+                cells.push(CellUpdate{
+                    col: idx as u32 % new.width,
+                    row: idx as u32 / new.width,
+                    glyph: *new_glyph,
+                });
+            }
+        }
+        ScreenUpdate::Cells(cells)
+    }
+}
+
+impl Screen {
+    /// A `width` by `height` screen with every cell set to `fill`.
+    pub fn new(width: u32, height: u32, fill: Glyph) -> Screen {
+        Screen {
+            glyphs: vec![fill; (width * height) as usize],
+            width: width,
+        }
+    }
+
+    /// (col, row) of every cell that blinks.
+    pub fn blinking_cells(&self) -> Vec<(u32, u32)> {
+        let width = self.width;
+        if width == 0 {
+            return Vec::new();
+        }
+        self.glyphs.iter().enumerate()
+            .filter(|&(_, glyph)| glyph.attributes.contains(Attributes::BLINK))
+            .map(|(idx, _)| (idx as u32 % width, idx as u32 / width))
+            .collect()
+    }
+
+    /// Number of rows, including a partially filled last row. The cells
+    /// missing from it are off the screen.
+    pub fn height(&self) -> u32 {
+        if self.width == 0 {
+            return 0;
+        }
+        (self.glyphs.len() as u32).div_ceil(self.width)
+    }
+
+    fn index(&self, col: u32, row: u32) -> Option<usize> {
+        let idx = (row as usize) * (self.width as usize) + col as usize;
+        if col < self.width && idx < self.glyphs.len() {
+            Some(idx)
+        } else {
+            None
+        }
+    }
+
+    /// The cells of a run of `width` starting at `start` that exist.
+    fn run(&self, start: usize, width: u32) -> Range<usize> {
+        let end = (start + width as usize).min(self.glyphs.len());
+        start.min(end)..end
+    }
+
+    pub fn get(&self, col: u32, row: u32) -> Option<&Glyph> {
+        self.index(col, row).map(|idx| &self.glyphs[idx])
+    }
+
+    pub fn get_mut(&mut self, col: u32, row: u32) -> Option<&mut Glyph> {
+        match self.index(col, row) {
+            Some(idx) => Some(&mut self.glyphs[idx]),
+            None => None,
+        }
+    }
+
+    /// Sets one cell. Returns false, changing nothing, if (col, row) is off
+    /// the screen.
+    pub fn set(&mut self, col: u32, row: u32, glyph: Glyph) -> bool {
+        match self.get_mut(col, row) {
+            Some(cell) => { *cell = glyph; true }
+            None => false,
+        }
+    }
+
+    /// Sets every cell in `rect` that is on the screen to `glyph`.
+    pub fn fill(&mut self, rect: Rect, glyph: Glyph) {
+        let rect = rect.clipped_to(self.width, self.height());
+        for row in rect.row..rect.row + rect.height {
+            let run = self.run((row * self.width + rect.col) as usize, rect.width);
+            for cell in &mut self.glyphs[run] {
+                *cell = glyph;
+            }
+        }
+    }
+
+    /// Copies the cells of `source` in `source_rect` so that its top left
+    /// corner lands on (col, row). Cells that fall off either screen are
+    /// skipped.
+    pub fn copy_from(&mut self, source: &Screen, source_rect: Rect, col: u32, row: u32) {
+        let source_rect = source_rect.clipped_to(source.width, source.height());
+        let dest_rect = Rect::new(col, row, source_rect.width, source_rect.height).clipped_to(self.width, self.height());
+        for y in 0..dest_rect.height {
+            let from = source.run(((source_rect.row + y) * source.width + source_rect.col) as usize, dest_rect.width);
+            let to = self.run(((dest_rect.row + y) * self.width + dest_rect.col) as usize, dest_rect.width);
+            let count = from.len().min(to.len());
+            self.glyphs[to.start..to.start + count].copy_from_slice(&source.glyphs[from.start..from.start + count]);
+        }
+    }
+
+    /// Moves the contents of `rect` right by `cols` and down by `rows`
+    /// (negative values move left and up). Contents moved out of `rect` are
+    /// lost, and cells uncovered are set to `fill`.
+    pub fn scroll(&mut self, rect: Rect, cols: i32, rows: i32, fill: Glyph) {
+        let rect = rect.clipped_to(self.width, self.height());
+        let before = self.clone();
+        self.fill(rect, fill);
+
+        let moved_width = rect.width.saturating_sub(cols.unsigned_abs());
+        let moved_height = rect.height.saturating_sub(rows.unsigned_abs());
+        if moved_width == 0 || moved_height == 0 {
+            return;
+        }
+
+        let source = Rect::new(
+            rect.col + if cols < 0 { cols.unsigned_abs() } else { 0 },
+            rect.row + if rows < 0 { rows.unsigned_abs() } else { 0 },
+            moved_width, moved_height);
+        let col = rect.col + if cols > 0 { cols as u32 } else { 0 };
+        let row = rect.row + if rows > 0 { rows as u32 } else { 0 };
+        self.copy_from(&before, source, col, row);
+    }
+
+    /// Changes the dimensions, keeping each cell that still fits at the same
+    /// (col, row) and setting new cells to `fill`.
+    pub fn resize(&mut self, width: u32, height: u32, fill: Glyph) {
+        let mut resized = Screen::new(width, height, fill);
+        resized.copy_from(self, Rect::new(0, 0, self.width, self.height()), 0, 0);
+        *self = resized;
+    }
+
+    /// Applies `update`, returning which cells changed.
+    pub fn apply(&mut self, update: ScreenUpdate) -> Damage {
+        match update {
+            ScreenUpdate::Full(screen) => {
+                *self = screen;
+                Damage::All
+            }
+            ScreenUpdate::Cells(cells) => {
+                let mut damaged = Vec::with_capacity(cells.len());
+                for cell in cells {
+                    if self.set(cell.col, cell.row, cell.glyph) {
+                        damaged.push((cell.col, cell.row));
+                    }
+                }
+                Damage::Cells(damaged)
+            }
+        }
+    }
+}
+
+/// Remembers the last screen sent so that only what changed is sent next
+/// time.
+///
+/// ```
+/// use gridui::GridUiInterface;
+/// use gridui::color::Color;
+/// use gridui::headless::HeadlessGridUi;
+/// use gridui::screen::{Attributes, Glyph, Screen, ScreenDiffer};
+///
+/// let gridui = HeadlessGridUi::default();
+/// let mut differ = ScreenDiffer::new();
+/// for character in 10..20 {
+///     let glyph = Glyph{ character: character, foreground: Color::BLACK, background: Color::WHITE, attributes: Attributes::NONE };
+///     let screen = Screen{ glyphs: vec![glyph; 4], width: 2 };
+///     gridui.send_update(differ.update_to(&screen));
+/// }
+/// ```
+pub struct ScreenDiffer {
+    last: Option<Screen>,
+}
+
+impl ScreenDiffer {
+    pub fn new() -> ScreenDiffer {
+        ScreenDiffer { last: None }
+    }
+
+    /// The update from the previous screen passed in to `screen`. The first
+    /// call, and any call after `reset`, gives a full update.
+    pub fn update_to(&mut self, screen: &Screen) -> ScreenUpdate {
+        let update = match self.last {
+            Some(ref last) => ScreenUpdate::between(last, screen),
+            None => ScreenUpdate::Full(screen.clone()),
+        };
+        self.last = Some(screen.clone());
+        update
+    }
+
+    /// Forgets the previous screen, e.g. after something else replaced it.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+impl Default for ScreenDiffer {
+    fn default() -> ScreenDiffer {
+        ScreenDiffer::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Attributes, CellUpdate, Damage, Glyph, Rect, Screen, ScreenDiffer, ScreenUpdate};
+    use color::Color;
+
+    fn glyph(character: u32) -> Glyph {
+        Glyph{ character: character, foreground: Color::BLACK, background: Color::WHITE, attributes: Attributes::NONE }
+    }
+
+    fn screen(characters: &[u32], width: u32) -> Screen {
+        Screen{ glyphs: characters.iter().map(|&c| glyph(c)).collect(), width: width }
+    }
+
+    fn characters(screen: &Screen) -> Vec<u32> {
+        screen.glyphs.iter().map(|g| g.character).collect()
+    }
+
+    #[test]
+    fn blinking() {
+        let mut screen = screen(&[1, 2, 3, 4], 2);
+        screen.glyphs[3].attributes = Attributes::BLINK | Attributes::INVERSE;
+        assert_eq!(screen.blinking_cells(), vec![(1, 1)]);
+
+        let blinking = screen.glyphs[3];
+        assert_eq!(blinking.as_shown(true), blinking);
+        let hidden = blinking.as_shown(false);
+        assert_eq!((hidden.character, hidden.foreground, hidden.background), (0, Color::BLACK, Color::BLACK));
+        assert_eq!(screen.glyphs[0].as_shown(false), screen.glyphs[0]);
+    }
+
+    #[test]
+    fn new_get_set() {
+        let mut s = Screen::new(3, 2, glyph(0));
+        assert_eq!(s.height(), 2);
+        assert_eq!(s.get(2, 1), Some(&glyph(0)));
+        assert_eq!(s.get(3, 0), None);
+        assert_eq!(s.get(0, 2), None);
+
+        assert!(s.set(1, 1, glyph(7)));
+        assert!(!s.set(3, 1, glyph(7)));
+        assert_eq!(characters(&s), [0, 0, 0, 0, 7, 0]);
+    }
+
+    #[test]
+    fn partial_row() {
+        let mut s = screen(&[1, 2, 3, 4, 5], 2);
+        assert_eq!(s.height(), 3);
+        assert_eq!(s.get(0, 2), Some(&glyph(5)));
+        assert_eq!(s.get(1, 2), None);
+        assert_eq!(s.apply(ScreenUpdate::Cells(vec![CellUpdate{ col: 0, row: 2, glyph: glyph(9) }])), Damage::Cells(vec![(0, 2)]));
+
+        s.scroll(Rect::new(0, 0, 2, 3), 0, 1, glyph(0));
+        assert_eq!(characters(&s), [0, 0, 1, 2, 3]);
+        s.fill(Rect::new(1, 2, 5, 5), glyph(8));
+        assert_eq!(characters(&s), [0, 0, 1, 2, 3]);
+        s.resize(3, 2, glyph(0));
+        assert_eq!(characters(&s), [0, 0, 0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn fill_clips() {
+        let mut s = Screen::new(3, 3, glyph(0));
+        s.fill(Rect::new(1, 1, 5, 5), glyph(1));
+        assert_eq!(characters(&s), [0, 0, 0, 0, 1, 1, 0, 1, 1]);
+
+        s.fill(Rect::new(4, 0, 1, 1), glyph(2));
+        assert_eq!(characters(&s), [0, 0, 0, 0, 1, 1, 0, 1, 1]);
+    }
+
+    #[test]
+    fn copy_from_clips() {
+        let source = screen(&[1, 2, 3, 4, 5, 6], 3);
+        let mut s = Screen::new(3, 3, glyph(0));
+        s.copy_from(&source, Rect::new(1, 0, 2, 2), 2, 1);
+        assert_eq!(characters(&s), [0, 0, 0, 0, 0, 2, 0, 0, 5]);
+
+        let mut s = Screen::new(2, 1, glyph(0));
+        s.copy_from(&source, Rect::new(2, 1, 9, 9), 0, 0);
+        assert_eq!(characters(&s), [6, 0]);
+    }
+
+    #[test]
+    fn scrolling() {
+        let start = screen(&[1, 2, 3, 4, 5, 6, 7, 8, 9], 3);
+
+        let mut up = start.clone();
+        up.scroll(Rect::new(0, 0, 3, 3), 0, -1, glyph(0));
+        assert_eq!(characters(&up), [4, 5, 6, 7, 8, 9, 0, 0, 0]);
+
+        let mut right = start.clone();
+        right.scroll(Rect::new(0, 1, 3, 2), 2, 0, glyph(0));
+        assert_eq!(characters(&right), [1, 2, 3, 0, 0, 4, 0, 0, 7]);
+
+        let mut gone = start.clone();
+        gone.scroll(Rect::new(1, 1, 2, 2), 0, 5, glyph(0));
+        assert_eq!(characters(&gone), [1, 2, 3, 4, 0, 0, 7, 0, 0]);
+    }
+
+    #[test]
+    fn resizing() {
+        let mut s = screen(&[1, 2, 3, 4], 2);
+        s.resize(3, 1, glyph(0));
+        assert_eq!(characters(&s), [1, 2, 0]);
+        s.resize(1, 2, glyph(9));
+        assert_eq!(characters(&s), [1, 9]);
+        assert_eq!(s.width, 1);
+    }
+
+    #[test]
+    fn diff_and_apply() {
+        let old = screen(&[1, 2, 3, 4], 2);
+        let new = screen(&[1, 2, 5, 4], 2);
+
+        let update = ScreenUpdate::between(&old, &new);
+        assert_eq!(update, ScreenUpdate::Cells(vec![CellUpdate{ col: 0, row: 1, glyph: glyph(5) }]));
+
+        let mut applied = old.clone();
+        assert_eq!(applied.apply(update), Damage::Cells(vec![(0, 1)]));
+        assert_eq!(applied, new);
+    }
+
+    #[test]
+    fn resize_is_full() {
+        let old = screen(&[1, 2, 3, 4], 2);
+        let new = screen(&[1, 2, 3, 4], 4);
+        assert_eq!(ScreenUpdate::between(&old, &new), ScreenUpdate::Full(new.clone()));
+
+        let mut applied = old.clone();
+        assert_eq!(applied.apply(ScreenUpdate::Full(new.clone())), Damage::All);
+        assert_eq!(applied, new);
+    }
+
+    #[test]
+    fn out_of_bounds_cells_ignored() {
+        let mut applied = screen(&[1, 2, 3, 4], 2);
+        let damage = applied.apply(ScreenUpdate::Cells(vec![
+            CellUpdate{ col: 2, row: 0, glyph: glyph(9) },
+            CellUpdate{ col: 0, row: 2, glyph: glyph(9) },
+        ]));
+        assert_eq!(damage, Damage::Cells(vec![]));
+        assert_eq!(applied, screen(&[1, 2, 3, 4], 2));
+    }
+
+    #[test]
+    fn differ() {
+        let mut differ = ScreenDiffer::new();
+        let first = screen(&[1, 2], 2);
+        assert_eq!(differ.update_to(&first), ScreenUpdate::Full(first.clone()));
+        assert_eq!(differ.update_to(&first), ScreenUpdate::Cells(vec![]));
+        differ.reset();
+        assert_eq!(differ.update_to(&first), ScreenUpdate::Full(first.clone()));
+    }
+}
@@ -0,0 +1,267 @@
+//! Golden-image tests for screens.
+//!
+//! `assert_snapshot` renders a `Screen` with the headless renderer and
+//! compares it pixel for pixel against a reference image checked in under
+//! `snapshots/`. References are binary PPM (P6) files, which need no image
+//! library to read or write and open in most image viewers.
+//!
+//! Run the tests with `GRIDUI_UPDATE_SNAPSHOTS=1` set to write the current
+//! rendering as the new reference instead of comparing against it.
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use atlas::GlyphAtlas;
+use color::Palette;
+use headless::{render_screen, Framebuffer};
+use screen::Screen;
+
+/// When this environment variable is set, snapshots are rewritten instead of
+/// checked.
+pub const UPDATE_ENV_VAR: &str = "GRIDUI_UPDATE_SNAPSHOTS";
+
+/// Color of pixels that differ in a diff image.
+const DIFF_PIXEL: [u8; 4] = [0xff, 0x00, 0xff, 0xff];
+
+/// Writes `framebuffer` as a binary PPM. Alpha is dropped.
+pub fn write_ppm<W: Write>(framebuffer: &Framebuffer, out: &mut W) -> io::Result<()> {
+    write!(out, "P6\n{} {}\n255\n", framebuffer.width(), framebuffer.height())?;
+    let mut rgb = Vec::with_capacity(framebuffer.pixels().len() / 4 * 3);
+    for pixel in framebuffer.pixels().chunks(4) {
+        rgb.extend_from_slice(&pixel[0..3]);
+    }
+    out.write_all(&rgb[..])
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Reads the next whitespace-delimited header field of a PPM, skipping
+/// comments. Returns the field and the offset just past it.
+fn ppm_header_field(data: &[u8], mut pos: usize) -> io::Result<(u32, usize)> {
+    loop {
+        match data.get(pos) {
+            Some(b'#') => {
+                while pos < data.len() && data[pos] != b'\n' {
+                    pos += 1;
+                }
+            }
+            Some(c) if c.is_ascii_whitespace() => { pos += 1; }
+            Some(_) => { break; }
+            None => { return Err(invalid("truncated PPM header")); }
+        }
+    }
+
+    let start = pos;
+    while pos < data.len() && data[pos].is_ascii_digit() {
+        pos += 1;
+    }
+    let field = std::str::from_utf8(&data[start..pos]).ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or_else(|| invalid("malformed PPM header"))?;
+    Ok((field, pos))
+}
+
+/// Reads a binary PPM with a maximum value of 255, as written by `write_ppm`.
+pub fn read_ppm<R: Read>(input: &mut R) -> io::Result<Framebuffer> {
+    let mut data = Vec::new();
+    input.read_to_end(&mut data)?;
+
+    if !data.starts_with(b"P6") {
+        return Err(invalid("not a binary PPM"));
+    }
+    let (width, pos) = ppm_header_field(&data, 2)?;
+    let (height, pos) = ppm_header_field(&data, pos)?;
+    let (max_value, pos) = ppm_header_field(&data, pos)?;
+    if max_value != 255 {
+        return Err(invalid("only 8-bit PPMs are supported"));
+    }
+
+    let rgba_len = width.checked_mul(height).and_then(|count| count.checked_mul(4))
+        .ok_or_else(|| invalid("PPM dimensions are too large"))? as usize;
+
+    // Exactly one whitespace character separates the header from the pixels.
+    let rgb = data.get(pos + 1..).unwrap_or(&[]);
+    if rgb.len() != rgba_len / 4 * 3 {
+        return Err(invalid("PPM pixel data does not match its dimensions"));
+    }
+
+    let mut pixels = Vec::with_capacity(rgba_len);
+    for pixel in rgb.chunks(3) {
+        pixels.extend_from_slice(pixel);
+        pixels.push(0xff);
+    }
+    Ok(Framebuffer::from_rgba(width, height, pixels))
+}
+
+/// Compares two images. Returns `None` if they are identical, otherwise an
+/// image the size of the larger of the two where differing pixels are
+/// magenta and matching pixels are a faded copy of `expected`.
+pub fn diff(expected: &Framebuffer, actual: &Framebuffer) -> Option<Framebuffer> {
+    if expected == actual {
+        return None;
+    }
+
+    let width = expected.width().max(actual.width());
+    let height = expected.height().max(actual.height());
+    let mut diff = Framebuffer::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let in_expected = x < expected.width() && y < expected.height();
+            let in_actual = x < actual.width() && y < actual.height();
+            let color = if in_expected && in_actual && expected.pixel(x, y) == actual.pixel(x, y) {
+                let [r, g, b, a] = expected.pixel(x, y);
+                [0xc0 + r/4, 0xc0 + g/4, 0xc0 + b/4, a]
+            } else {
+                DIFF_PIXEL
+            };
+            diff.set_pixel(x, y, color);
+        }
+    }
+    Some(diff)
+}
+
+/// The directory references are kept in: `snapshots/` under the root of the
+/// crate being tested.
+pub fn snapshot_dir() -> PathBuf {
+    let root = env::var_os("CARGO_MANIFEST_DIR").unwrap_or_else(|| ".".into());
+    Path::new(&root).join("snapshots")
+}
+
+fn save(framebuffer: &Framebuffer, path: &Path) {
+    let mut file = File::create(path)
+        .unwrap_or_else(|e| panic!("could not create {}: {}", path.display(), e));
+    write_ppm(framebuffer, &mut file)
+        .unwrap_or_else(|e| panic!("could not write {}: {}", path.display(), e));
+}
+
+/// Renders `screen` with the built-in atlas and default palette and checks it against the
+/// reference `<name>.ppm` in `dir`.
+///
+/// On a mismatch, `<name>.actual.ppm` and `<name>.diff.ppm` are written next
+/// to the reference and the test panics.
+pub fn assert_snapshot_in(dir: &Path, name: &str, screen: &Screen) {
+    let actual = render_screen(&GlyphAtlas::builtin(), &Palette::default(), screen);
+    let reference = dir.join(format!("{}.ppm", name));
+
+    if env::var_os(UPDATE_ENV_VAR).is_some() {
+        fs::create_dir_all(dir)
+            .unwrap_or_else(|e| panic!("could not create {}: {}", dir.display(), e));
+        save(&actual, &reference);
+        return;
+    }
+
+    let expected = match File::open(&reference).and_then(|mut f| read_ppm(&mut f)) {
+        Ok(expected) => expected,
+        Err(e) => panic!("could not read snapshot {}: {}. Run with {}=1 to create it.",
+                         reference.display(), e, UPDATE_ENV_VAR),
+    };
+
+    if let Some(diff) = diff(&expected, &actual) {
+        let actual_path = dir.join(format!("{}.actual.ppm", name));
+        let diff_path = dir.join(format!("{}.diff.ppm", name));
+        save(&actual, &actual_path);
+        save(&diff, &diff_path);
+        panic!("screen does not match snapshot {}; see {} and {}. Run with {}=1 to accept the new rendering.",
+               reference.display(), actual_path.display(), diff_path.display(), UPDATE_ENV_VAR);
+    }
+}
+
+/// `assert_snapshot_in` with the default `snapshot_dir()`.
+pub fn assert_snapshot(name: &str, screen: &Screen) {
+    assert_snapshot_in(&snapshot_dir(), name, screen);
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+
+    use super::{assert_snapshot, diff, read_ppm, write_ppm};
+    use color::Color;
+    use glyphcode;
+    use headless::Framebuffer;
+    use screen::{Attributes, Glyph, Screen};
+
+    #[test]
+    fn ppm_round_trip() {
+        let mut framebuffer = Framebuffer::new(3, 2);
+        framebuffer.set_pixel(1, 0, [0x12, 0x34, 0x56, 0xff]);
+        framebuffer.set_pixel(2, 1, [0x00, 0x00, 0x00, 0xff]);
+
+        let mut ppm = Vec::new();
+        write_ppm(&framebuffer, &mut ppm).unwrap();
+        assert!(ppm.starts_with(b"P6\n3 2\n255\n"));
+        assert_eq!(read_ppm(&mut &ppm[..]).unwrap(), framebuffer);
+    }
+
+    #[test]
+    fn ppm_comments() {
+        let ppm = b"P6 # a comment\n1\n# another\n1 255\n\x01\x02\x03";
+        assert_eq!(read_ppm(&mut &ppm[..]).unwrap().pixel(0, 0), [1, 2, 3, 0xff]);
+        assert!(read_ppm(&mut &b"P6\n2 2\n255\n\x00"[..]).is_err());
+        let huge = read_ppm(&mut &b"P6\n4294967295 4294967295\n255\n\x00"[..]).unwrap_err();
+        assert_eq!(huge.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn diff_marks_changes() {
+        let expected = Framebuffer::new(2, 1);
+        let mut actual = expected.clone();
+        assert!(diff(&expected, &actual).is_none());
+
+        actual.set_pixel(1, 0, [0, 0, 0, 0xff]);
+        let diff = diff(&expected, &actual).unwrap();
+        assert_eq!(diff.pixel(0, 0), [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(diff.pixel(1, 0), [0xff, 0x00, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn digits() {
+        let glyphs = (10..20).map(|character| Glyph{ character: character, foreground: Color::Rgb(0x000000), background: Color::Rgb(0xffffff), attributes: Attributes::NONE }).collect();
+        assert_snapshot("digits", &Screen{ glyphs: glyphs, width: 5 });
+    }
+
+    #[test]
+    fn symbols_and_colors() {
+        let glyphs = (0..10).map(|character| Glyph{
+            character: character,
+            foreground: Color::Rgb(0x1f * character),
+            background: Color::Rgb(0xffffff - 0x170000 * character),
+            attributes: Attributes::NONE,
+        }).collect();
+        assert_snapshot("symbols_and_colors", &Screen{ glyphs: glyphs, width: 4 });
+    }
+
+    #[test]
+    fn accents() {
+        let glyphs = glyphcode::from_str("\u{e7}\u{e5}\u{c5}\u{e9}\u{ef}\u{10f}\u{f1}\u{160}").unwrap().into_iter()
+            .map(|character| Glyph{ character: character, foreground: Color::Rgb(0xffffff), background: Color::Rgb(0x203040), attributes: Attributes::NONE })
+            .collect();
+        assert_snapshot("accents", &Screen{ glyphs: glyphs, width: 4 });
+    }
+
+    #[test]
+    fn attributes() {
+        let styles = [Attributes::NONE, Attributes::BOLD, Attributes::ITALIC, Attributes::UNDERLINE,
+                      Attributes::STRIKETHROUGH, Attributes::INVERSE, Attributes::BOLD | Attributes::ITALIC];
+        let text = glyphcode::from_str("Hg").unwrap();
+        let glyphs = styles.iter().flat_map(|&attributes| text.iter().map(move |&character| Glyph{
+            character: character,
+            foreground: Color::Rgb(0x000000),
+            background: Color::Rgb(0xffffff),
+            attributes: attributes,
+        })).collect();
+        assert_snapshot("attributes", &Screen{ glyphs: glyphs, width: 2 });
+    }
+
+    #[test]
+    fn missing_characters() {
+        let glyphs = [0xfffd, 0x1234abcd, 0xffffffff, 0x100f].iter()
+            .map(|&character| Glyph{ character: character, foreground: Color::Rgb(0x000000), background: Color::Rgb(0xffffff), attributes: Attributes::NONE })
+            .collect();
+        assert_snapshot("missing_characters", &Screen{ glyphs: glyphs, width: 4 });
+    }
+}
@@ -0,0 +1,244 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use color::Color;
+use glyphcode::{self, GlyphCode};
+use screen::{Attributes, Glyph, Rect, Screen};
+
+const SPACE: GlyphCode = 0;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Wrap {
+    /// Lines longer than the rectangle are cut off.
+    None,
+    /// Lines break at the edge of the rectangle, wherever that falls.
+    Character,
+    /// Lines break between words. Words too long for a line on their own are
+    /// broken wherever they must be.
+    Word,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// Where and how `Screen::print` lays out text.
+#[derive(Clone, Debug)]
+pub struct TextLayout {
+    /// Text starts at the top left of this rectangle and is clipped to it.
+    pub rect: Rect,
+    pub foreground: Color,
+    pub background: Color,
+    pub attributes: Attributes,
+    pub wrap: Wrap,
+    pub align: Align,
+    /// Shown in place of graphemes `glyphcode` can't represent.
+    pub fallback: GlyphCode,
+}
+
+impl TextLayout {
+    /// Unwrapped, left-aligned text in `rect`.
+    pub fn new(rect: Rect, foreground: Color, background: Color) -> TextLayout {
+        TextLayout {
+            rect: rect,
+            foreground: foreground,
+            background: background,
+            attributes: Attributes::NONE,
+            wrap: Wrap::None,
+            align: Align::Left,
+            fallback: glyphcode::REPLACEMENT,
+        }
+    }
+
+    pub fn wrap(mut self, wrap: Wrap) -> TextLayout {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn align(mut self, align: Align) -> TextLayout {
+        self.align = align;
+        self
+    }
+
+    pub fn attributes(mut self, attributes: Attributes) -> TextLayout {
+        self.attributes = attributes;
+        self
+    }
+
+    pub fn fallback(mut self, fallback: GlyphCode) -> TextLayout {
+        self.fallback = fallback;
+        self
+    }
+}
+
+/// What `Screen::print` did.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Printed {
+    /// Cells written.
+    pub cells: u32,
+    /// Lines written, including blank ones.
+    pub lines: u32,
+    /// Whether some of the text didn't fit in the rectangle.
+    pub clipped: bool,
+}
+
+/// Converts `text` to glyphs, one list per line.
+fn paragraphs(text: &str, fallback: GlyphCode) -> Vec<Vec<GlyphCode>> {
+    let mut paragraphs = vec![Vec::new()];
+    for grapheme in UnicodeSegmentation::graphemes(text, true) {
+        if grapheme == "\n" || grapheme == "\r\n" {
+            paragraphs.push(Vec::new());
+            continue;
+        }
+
+        paragraphs.last_mut().unwrap().push(glyphcode::from_grapheme(grapheme).unwrap_or(fallback));
+    }
+    paragraphs
+}
+
+/// Splits one paragraph into lines at most `width` long. Returns the lines
+/// and whether anything was cut off.
+fn wrap_paragraph(paragraph: &[GlyphCode], width: usize, wrap: Wrap) -> (Vec<&[GlyphCode]>, bool) {
+    if paragraph.is_empty() {
+        return (vec![paragraph], false);
+    }
+    if width == 0 {
+        return (Vec::new(), true);
+    }
+
+    match wrap {
+        Wrap::None => {
+            let len = paragraph.len().min(width);
+            (vec![&paragraph[..len]], len < paragraph.len())
+        }
+        Wrap::Character => (paragraph.chunks(width).collect(), false),
+        Wrap::Word => {
+            let mut lines = Vec::new();
+            let mut rest = paragraph;
+            while !rest.is_empty() {
+                if rest.len() <= width {
+                    lines.push(rest);
+                    break;
+                }
+
+                // Break at the last space that keeps the line short enough,
+                // or mid-word if there is none.
+                let (line, next) = match rest[..width + 1].iter().rposition(|&g| g == SPACE) {
+                    Some(0) | None => (&rest[..width], &rest[width..]),
+                    Some(space) => (&rest[..space], &rest[space..]),
+                };
+                lines.push(line);
+
+                // The spaces at the break aren't shown on either line.
+                let skip = next.iter().take_while(|&&g| g == SPACE).count();
+                rest = &next[skip..];
+            }
+            (lines, false)
+        }
+    }
+}
+
+impl Screen {
+    /// Writes `text` into the cells in `layout.rect`, breaking lines at
+    /// newlines and as `layout.wrap` says. Text that doesn't fit in the
+    /// rectangle, or on the screen, is dropped.
+    pub fn print(&mut self, text: &str, layout: &TextLayout) -> Printed {
+        let rect = layout.rect;
+        let mut printed = Printed { cells: 0, lines: 0, clipped: false };
+
+        for paragraph in paragraphs(text, layout.fallback) {
+            let (lines, clipped) = wrap_paragraph(&paragraph, rect.width as usize, layout.wrap);
+            printed.clipped |= clipped;
+
+            for line in lines {
+                if printed.lines >= rect.height {
+                    printed.clipped = true;
+                    return printed;
+                }
+
+                let slack = rect.width - line.len() as u32;
+                let indent = match layout.align {
+                    Align::Left => 0,
+                    Align::Center => slack / 2,
+                    Align::Right => slack,
+                };
+                for (idx, &character) in line.iter().enumerate() {
+                    let glyph = Glyph { character: character, foreground: layout.foreground, background: layout.background, attributes: layout.attributes };
+                    if self.set(rect.col + indent + idx as u32, rect.row + printed.lines, glyph) {
+                        printed.cells += 1;
+                    }
+                }
+                printed.lines += 1;
+            }
+        }
+        printed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Align, Printed, TextLayout, Wrap};
+    use color::Color;
+    use glyphcode;
+    use screen::{Attributes, Glyph, Rect, Screen};
+
+    const BLANK: Glyph = Glyph { character: 0, foreground: Color::BLACK, background: Color::BLACK, attributes: Attributes::NONE };
+
+    /// The screen's text, one string per row, with '~' for the fallback.
+    fn rows(screen: &Screen) -> Vec<String> {
+        screen.glyphs.chunks(screen.width as usize).map(|row| {
+            row.iter().map(|g| glyphcode::as_char(g.character).unwrap_or('~')).collect()
+        }).collect()
+    }
+
+    fn print(text: &str, width: u32, height: u32, wrap: Wrap, align: Align) -> (Vec<String>, Printed) {
+        let mut screen = Screen::new(width, height, BLANK);
+        let layout = TextLayout::new(Rect::new(0, 0, width, height), Color::BLACK, Color::BLACK).wrap(wrap).align(align);
+        let printed = screen.print(text, &layout);
+        (rows(&screen), printed)
+    }
+
+    #[test]
+    fn unwrapped_clips() {
+        let (rows, printed) = print("hello world\nhi", 5, 3, Wrap::None, Align::Left);
+        assert_eq!(rows, ["hello", "hi   ", "     "]);
+        assert_eq!(printed, Printed { cells: 7, lines: 2, clipped: true });
+    }
+
+    #[test]
+    fn character_wrap() {
+        let (rows, printed) = print("abcdefg", 3, 3, Wrap::Character, Align::Left);
+        assert_eq!(rows, ["abc", "def", "g  "]);
+        assert_eq!(printed, Printed { cells: 7, lines: 3, clipped: false });
+    }
+
+    #[test]
+    fn word_wrap() {
+        let (rows, _) = print("the quick  brown fox", 7, 4, Wrap::Word, Align::Left);
+        assert_eq!(rows, ["the    ", "quick  ", "brown  ", "fox    "]);
+
+        let (rows, printed) = print("a verylongword", 4, 3, Wrap::Word, Align::Left);
+        assert_eq!(rows, ["a   ", "very", "long"]);
+        assert!(printed.clipped);
+    }
+
+    #[test]
+    fn alignment() {
+        let (rows, _) = print("ab\nabc", 6, 2, Wrap::None, Align::Center);
+        assert_eq!(rows, ["  ab  ", " abc  "]);
+
+        let (rows, _) = print("ab", 6, 1, Wrap::None, Align::Right);
+        assert_eq!(rows, ["    ab"]);
+    }
+
+    #[test]
+    fn fallback_and_offset() {
+        let mut screen = Screen::new(6, 2, BLANK);
+        let layout = TextLayout::new(Rect::new(2, 1, 10, 10), Color::BLACK, Color::BLACK);
+        let printed = screen.print("a\u{1F600}q\u{301}", &layout);
+        assert_eq!(rows(&screen), ["      ", "  a~~ "]);
+        assert_eq!(printed, Printed { cells: 3, lines: 1, clipped: false });
+    }
+}
@@ -7,19 +7,27 @@ use std::ptr::{
   null,
   null_mut,
 };
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Range;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Instant;
 
 use libc::{self, c_uint};
-use x11_dl::xlib;
+use x11_dl::{keysym, xlib, xshm};
 
-use input_event::InputEvent;
-use screen::{Screen};
+use input_event::{InputEvent, Key, Modifiers, MouseButton, MousePosition};
+use interface::GridUiInterface;
+use atlas::GlyphAtlas;
+use color::Palette;
+use config::{GridUiConfig, Rendering, MAX_GRID_PIXELS};
+use headless::{self, Framebuffer};
+use screen::{Attributes, Cursor, CursorShape, Damage, Glyph, Screen, ScreenUpdate};
 use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
-use glyph_parts::glyph_to_parts;
+use glyphcode;
 
-const TITLE: &'static str = "Hello World!";
-const DEFAULT_WIDTH: c_uint = 640;
-const DEFAULT_HEIGHT: c_uint = 480;
 
 #[repr(C)]
 struct pollfd {
@@ -42,15 +50,21 @@ extern "C" {
 
 
 pub struct GridUi {
-    screen_sink: Sender<Screen>,
+    screen_sink: Sender<ScreenUpdate>,
+    title_sink: Sender<String>,
+    palette_sink: Sender<Palette>,
+    cursor_sink: Sender<Cursor>,
     pub input_event_source: Receiver<InputEvent>,
 
     /// Pipe file descriptor. Writing to this signals the UI thread to check screen_source
     write_pipe: libc::c_int,
+
+    /// Columns and rows that fit in the window, kept up to date by the UI thread.
+    grid_size: Arc<Mutex<(u32, u32)>>,
 }
 
 impl GridUi {
-    pub fn new() -> GridUi {
+    pub fn new(config: GridUiConfig) -> GridUi {
         let (read_pipe, write_pipe) = {
             let mut pipes: [libc::c_int;2] = [0,0];
             unsafe { pipe2(pipes.as_mut_ptr(), 2048 /* O_NONBLOCK*/ ); }
@@ -59,22 +73,41 @@ impl GridUi {
         };
 
         let (screen_sink, screen_source) = channel();
+        let (title_sink, title_source) = channel();
+        let (palette_sink, palette_source) = channel();
+        let (cursor_sink, cursor_source) = channel();
         let (input_event_sink, input_event_source) = channel();
 
+        // The window starts out at the configured size, within its limits. If
+        // the window manager picks another, the UI thread will update this.
+        let grid_size = Arc::new(Mutex::new(config.clamp_grid_size(config.grid_size)));
+
+        let ui_grid_size = grid_size.clone();
         thread::spawn(move || {
-            unsafe { ui_main(read_pipe, screen_source, input_event_sink); }
+            let sources = Sources {
+                screens: screen_source,
+                titles: title_source,
+                palettes: palette_source,
+                cursors: cursor_source,
+            };
+            unsafe { ui_main(read_pipe, sources, input_event_sink, ui_grid_size, config); }
         });
 
         GridUi{
             screen_sink: screen_sink,
+            title_sink: title_sink,
+            palette_sink: palette_sink,
+            cursor_sink: cursor_sink,
             input_event_source: input_event_source,
             write_pipe: write_pipe,
+            grid_size: grid_size,
         }
     }
+}
 
-    pub fn send_screen(&mut self, screen: Screen) {
-        self.screen_sink.send(screen).ok().expect("Could not send screen to window, which has unexpectedly closed");
-
+impl GridUi {
+    /// Signals the UI thread to check for new screens and titles.
+    fn wake_ui_thread(&self) {
         let buf = [0u8];
         unsafe {
             write(self.write_pipe, buf.as_ptr() as *const libc::c_void, 1); 
@@ -82,13 +115,896 @@ impl GridUi {
     }
 }
 
-unsafe fn ui_main(signal_fd: libc::c_int, screen_source: Receiver<Screen>, input_event_sink: Sender<InputEvent>) {
+impl GridUiInterface for GridUi {
+    fn send_update(&self, update: ScreenUpdate) {
+        // Once the window has closed there is nothing to show this on.
+        self.screen_sink.send(update).ok();
+        self.wake_ui_thread();
+    }
+
+    fn set_palette(&self, palette: Palette) {
+        self.palette_sink.send(palette).ok();
+        self.wake_ui_thread();
+    }
+
+    fn set_cursor(&self, cursor: Cursor) {
+        self.cursor_sink.send(cursor).ok();
+        self.wake_ui_thread();
+    }
+
+    fn set_title(&self, title: &str) {
+        self.title_sink.send(title.to_string()).ok();
+        self.wake_ui_thread();
+    }
+
+    fn get_input_event(&self) -> InputEvent {
+        self.input_event_source.recv().unwrap_or(InputEvent::Close)
+    }
+
+    fn try_get_input_event(&self) -> Option<InputEvent> {
+        self.input_event_source.try_recv().ok()
+    }
+
+    fn grid_size(&self) -> (u32, u32) {
+        *self.grid_size.lock().unwrap()
+    }
+}
+
+impl Default for GridUi {
+    fn default() -> GridUi {
+        GridUi::new(GridUiConfig::default())
+    }
+}
+
+/// Maps a keysym to a key, the same way the Windows backend maps virtual key
+/// codes. Letters map to lowercase whatever the shift state.
+fn keysym_to_key(keysym: libc::c_ulong) -> Option<Key> {
+    let keysym = keysym as c_uint;
+    if (keysym::XK_a <= keysym && keysym <= keysym::XK_z) || (keysym::XK_A <= keysym && keysym <= keysym::XK_Z) ||
+       (keysym::XK_0 <= keysym && keysym <= keysym::XK_9) || keysym == keysym::XK_space {
+        // These keysyms are the same as their ASCII codes.
+        return glyphcode::from_char((keysym as u8 as char).to_ascii_lowercase()).map(Key::Character);
+    }
+
+    if keysym::XK_F1 <= keysym && keysym <= keysym::XK_F12 {
+        return Some(Key::Function((keysym - keysym::XK_F1 + 1) as u8));
+    }
+
+    Some(match keysym {
+        keysym::XK_Up | keysym::XK_KP_Up => Key::Up,
+        keysym::XK_Down | keysym::XK_KP_Down => Key::Down,
+        keysym::XK_Left | keysym::XK_KP_Left => Key::Left,
+        keysym::XK_Right | keysym::XK_KP_Right => Key::Right,
+        keysym::XK_Return | keysym::XK_KP_Enter => Key::Enter,
+        keysym::XK_Escape => Key::Escape,
+        keysym::XK_Tab | keysym::XK_ISO_Left_Tab => Key::Tab,
+        keysym::XK_BackSpace => Key::Backspace,
+        keysym::XK_Delete | keysym::XK_KP_Delete => Key::Delete,
+        keysym::XK_Home | keysym::XK_KP_Home => Key::Home,
+        keysym::XK_End | keysym::XK_KP_End => Key::End,
+        keysym::XK_Page_Up | keysym::XK_KP_Page_Up => Key::PageUp,
+        keysym::XK_Page_Down | keysym::XK_KP_Page_Down => Key::PageDown,
+        _ => { return None; }
+    })
+}
+
+/// Reads the modifiers out of the `state` of an X key or button event.
+fn state_to_modifiers(state: c_uint) -> Modifiers {
+    let mut modifiers = Modifiers::NONE;
+    if state & xlib::ShiftMask != 0 { modifiers = modifiers | Modifiers::SHIFT; }
+    if state & xlib::ControlMask != 0 { modifiers = modifiers | Modifiers::CONTROL; }
+    if state & xlib::Mod1Mask != 0 { modifiers = modifiers | Modifiers::ALT; }
+    if state & xlib::Mod4Mask != 0 { modifiers = modifiers | Modifiers::SUPER; }
+    modifiers
+}
+
+/// X reports the wheel as presses of buttons 4 through 7. Returns the
+/// (horizontal, vertical) notches a button stands for, if it is a wheel button.
+fn button_to_wheel(button: c_uint) -> Option<(i32, i32)> {
+    match button {
+        4 => Some((0, 1)),
+        5 => Some((0, -1)),
+        6 => Some((-1, 0)),
+        7 => Some((1, 0)),
+        _ => None,
+    }
+}
+
+fn button_to_mouse_button(button: c_uint) -> MouseButton {
+    match button {
+        1 => MouseButton::Left,
+        2 => MouseButton::Middle,
+        3 => MouseButton::Right,
+        // 8 and up follow the wheel buttons.
+        n => MouseButton::Extra((n.saturating_sub(7)).min(255) as u8),
+    }
+}
+
+/// The text typed by a key press, or an empty string for keys like Enter
+/// that don't type anything. Goes through the input method if there is one.
+unsafe fn lookup_text(xlib: &xlib::Xlib, input_context: xlib::XIC, key_event: &mut xlib::XKeyEvent) -> String {
+    let mut buf = vec![0u8; 32];
+    let mut keysym = 0;
+    let bytes = if input_context.is_null() {
+        // Without an input method the text comes back in Latin-1.
+        let len = (xlib.XLookupString)(key_event, buf.as_mut_ptr() as *mut libc::c_char, buf.len() as libc::c_int,
+                                       &mut keysym, null_mut());
+        buf[..len.max(0) as usize].iter().map(|&b| b as char).collect::<String>().into_bytes()
+    } else {
+        let mut status = 0;
+        let mut len = (xlib.Xutf8LookupString)(input_context, key_event, buf.as_mut_ptr() as *mut libc::c_char,
+                                               buf.len() as libc::c_int, &mut keysym, &mut status);
+        if status == xlib::XBufferOverflow {
+            buf = vec![0u8; len as usize];
+            len = (xlib.Xutf8LookupString)(input_context, key_event, buf.as_mut_ptr() as *mut libc::c_char,
+                                           buf.len() as libc::c_int, &mut keysym, &mut status);
+        }
+        if status != xlib::XLookupChars && status != xlib::XLookupBoth {
+            len = 0;
+        }
+        buf.truncate(len.max(0) as usize);
+        buf
+    };
+
+    String::from_utf8_lossy(&bytes).chars().filter(|ch| !ch.is_control()).collect()
+}
+
+/// Sets both the legacy window name and the UTF-8 one modern window managers
+/// show.
+unsafe fn set_title(xlib: &xlib::Xlib, display: *mut xlib::Display, window: xlib::Window, title: &str) {
+    let title_str = CString::new(title.replace('\0', "")).unwrap();
+    (xlib.XStoreName)(display, window, title_str.as_ptr() as *mut _);
+
+    let net_wm_name = (xlib.XInternAtom)(display, b"_NET_WM_NAME\0".as_ptr() as *const libc::c_char, xlib::False);
+    let utf8_string = (xlib.XInternAtom)(display, b"UTF8_STRING\0".as_ptr() as *const libc::c_char, xlib::False);
+    let bytes = title_str.as_bytes();
+    (xlib.XChangeProperty)(display, window, net_wm_name, utf8_string, 8, xlib::PropModeReplace,
+                           bytes.as_ptr(), bytes.len() as libc::c_int);
+}
+
+/// Tells the window manager to resize in whole cells within the configured
+/// limits.
+unsafe fn set_size_hints(xlib: &xlib::Xlib, display: *mut xlib::Display, window: xlib::Window,
+                         config: &GridUiConfig, cell_width: u32, cell_height: u32) {
+    let hints = (xlib.XAllocSizeHints)();
+    if hints.is_null() {
+        return;
+    }
+
+    let (min, max) = (config.effective_min_grid_size(), config.effective_max_grid_size());
+    (*hints).flags = xlib::PMinSize | xlib::PMaxSize | xlib::PResizeInc | xlib::PBaseSize;
+    (*hints).base_width = 0;
+    (*hints).base_height = 0;
+    (*hints).width_inc = cell_width as libc::c_int;
+    (*hints).height_inc = cell_height as libc::c_int;
+    (*hints).min_width = (min.0 * cell_width) as libc::c_int;
+    (*hints).min_height = (min.1 * cell_height) as libc::c_int;
+    (*hints).max_width = (max.0 * cell_width) as libc::c_int;
+    (*hints).max_height = (max.1 * cell_height) as libc::c_int;
+
+    (xlib.XSetWMNormalHints)(display, window, hints);
+    (xlib.XFree)(hints as *mut libc::c_void);
+}
+
+/// A (foreground, background) pair, each 0x00RRGGBB, and the cells drawn in
+/// it.
+type ColorGroup = ((u32, u32), Vec<(u32, u32)>);
+
+/// Everything besides the screen that decides how cells are drawn.
+struct Look<'a> {
+    palette: &'a Palette,
+    /// Whether blinking glyphs are in their visible phase.
+    blink_visible: bool,
+    /// The cursor, if it is drawn right now.
+    cursor: Option<Cursor>,
+}
+
+impl<'a> Look<'a> {
+    /// The glyph at (col, row) as it is drawn right now, inverted if a block
+    /// cursor is on it.
+    fn glyph(&self, screen: &Screen, col: u32, row: u32) -> Glyph {
+        let glyph = screen.glyphs[(row * screen.width + col) as usize].as_shown(self.blink_visible);
+        match self.cursor_at(col, row) {
+            Some(cursor) => cursor.over(glyph),
+            None => glyph,
+        }
+    }
+
+    fn cursor_at(&self, col: u32, row: u32) -> Option<Cursor> {
+        self.cursor.filter(|cursor| (cursor.col, cursor.row) == (col, row))
+    }
+
+    /// The shape of the cursor's line to draw over the glyph at (col, row),
+    /// if it is there and not a block.
+    fn cursor_line(&self, col: u32, row: u32) -> Option<CursorShape> {
+        self.cursor_at(col, row).map(|cursor| cursor.shape).filter(|&shape| shape != CursorShape::Block)
+    }
+}
+
+/// Sorts `cells` into runs sharing a (foreground, background) pair, so each
+/// pair's GC colors are set only once.
+fn group_by_colors(screen: &Screen, look: &Look, cells: &[(u32, u32)]) -> Vec<ColorGroup> {
+    let mut groups: Vec<ColorGroup> = Vec::new();
+    let mut sorted: Vec<_> = cells.iter().map(|&(col, row)| {
+        let (foreground, background) = look.glyph(screen, col, row).colors();
+        ((look.palette.rgb(foreground), look.palette.rgb(background)), (col, row))
+    }).collect();
+    sorted.sort();
+    sorted.dedup();
+    for (colors, cell) in sorted {
+        match groups.last_mut() {
+            Some(&mut (last, ref mut group)) if last == colors => { group.push(cell); continue; }
+            _ => {}
+        }
+        groups.push((colors, vec![cell]));
+    }
+    groups
+}
+
+/// Keeps a drawn copy of the screen that exposed and changed parts of the
+/// window are shown from, so the window never shows a half-drawn cell.
+trait Renderer {
+    /// Width and height of a cell in pixels.
+    fn cell_size(&self) -> (u32, u32);
+
+    /// Redraws all of `screen`, which may have changed size.
+    unsafe fn draw_screen(&mut self, screen: &Screen, look: &Look);
+
+    /// Redraws `cells` of `screen`.
+    unsafe fn draw_cells(&mut self, screen: &Screen, look: &Look, cells: &[(u32, u32)]);
+
+    /// Shows a rectangle of the window, from the drawn screen where it
+    /// covers it and the window background elsewhere.
+    unsafe fn copy_to_window(&mut self, x: i32, y: i32, width: u32, height: u32);
+
+    /// Shows the cells in the smallest rectangle holding all of `cells`.
+    unsafe fn copy_cells_to_window(&mut self, cells: &[(u32, u32)]) {
+        let first_col = cells.iter().map(|cell| cell.0).min();
+        let first_row = cells.iter().map(|cell| cell.1).min();
+        let last_col = cells.iter().map(|cell| cell.0).max();
+        let last_row = cells.iter().map(|cell| cell.1).max();
+        if let (Some(first_col), Some(first_row), Some(last_col), Some(last_row)) = (first_col, first_row, last_col, last_row) {
+            let (cell_width, cell_height) = self.cell_size();
+            self.copy_to_window((first_col * cell_width) as i32, (first_row * cell_height) as i32,
+                                (last_col + 1 - first_col) * cell_width, (last_row + 1 - first_row) * cell_height);
+        }
+    }
+
+    unsafe fn free(&mut self);
+}
+
+/// Clears whatever of a rectangle of the window lies right of or below a
+/// drawn screen of `size`, and returns the part within it, if any.
+unsafe fn clear_outside(xlib: &xlib::Xlib, display: *mut xlib::Display, window: xlib::Window, size: (u32, u32),
+                        (x, y, width, height): (i32, i32, u32, u32)) -> Option<(i32, i32, u32, u32)> {
+    let (right, bottom) = (x + width as i32, y + height as i32);
+    let (buffer_width, buffer_height) = (size.0 as i32, size.1 as i32);
+
+    if right > buffer_width {
+        let left = x.max(buffer_width);
+        (xlib.XClearArea)(display, window, left, y, (right - left) as c_uint, height, xlib::False);
+    }
+    if bottom > buffer_height {
+        let top = y.max(buffer_height);
+        (xlib.XClearArea)(display, window, x, top, width, (bottom - top) as c_uint, xlib::False);
+    }
+
+    let (left, top) = (x.max(0), y.max(0));
+    let (right, bottom) = (right.min(buffer_width), bottom.min(buffer_height));
+    if left < right && top < bottom {
+        Some((left, top, (right - left) as u32, (bottom - top) as u32))
+    } else {
+        None
+    }
+}
+
+/// The screen drawn by the X server into an off-screen pixmap, which
+/// exposed and changed parts of the window are copied from.
+struct BackBuffer<'a> {
+    xlib: &'a xlib::Xlib,
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    depth: c_uint,
+    gc: xlib::GC,
+    pixels: Pixels<'a>,
+    atlas: &'a GlyphAtlas,
+    /// Every atlas part stacked vertically, as a 1-bit pixmap.
+    glyph_bitmap: xlib::Pixmap,
+    /// 0 until there's something to draw.
+    pixmap: xlib::Pixmap,
+    /// Size of `pixmap` in pixels.
+    size: (u32, u32),
+}
+
+impl<'a> BackBuffer<'a> {
+    unsafe fn new(xlib: &'a xlib::Xlib, display: *mut xlib::Display, window: xlib::Window, screen_num: libc::c_int,
+                  atlas: &'a GlyphAtlas) -> BackBuffer<'a> {
+        let glyph_bitmap = (xlib.XCreateBitmapFromData)(display, window, atlas.bits().as_ptr() as *const i8,
+                                                         atlas.part_width(), atlas.part_height()*atlas.part_count());
+        let gc = (xlib.XCreateGC)(display, window, 0, null_mut());
+        // Copies within our own pixmaps never need redrawing.
+        (xlib.XSetGraphicsExposures)(display, gc, xlib::False);
+        (xlib.XSetStipple)(display, gc, glyph_bitmap);
+        BackBuffer {
+            xlib: xlib,
+            display: display,
+            window: window,
+            depth: (xlib.XDefaultDepth)(display, screen_num) as c_uint,
+            gc: gc,
+            pixels: Pixels::new(xlib, display, screen_num),
+            atlas: atlas,
+            glyph_bitmap: glyph_bitmap,
+            pixmap: 0,
+            size: (0, 0),
+        }
+    }
+
+    /// Makes the pixmap fit `screen`. Its contents are undefined afterwards.
+    unsafe fn fit(&mut self, screen: &Screen) {
+        let size = drawn_size(screen, self.cell_size());
+        if size == self.size {
+            return;
+        }
+        if self.pixmap != 0 {
+            (self.xlib.XFreePixmap)(self.display, self.pixmap);
+            self.pixmap = 0;
+        }
+        if size.0 > 0 && size.1 > 0 {
+            self.pixmap = (self.xlib.XCreatePixmap)(self.display, self.window, size.0, size.1, self.depth);
+        }
+        self.size = size;
+    }
+
+    /// Draws the parts and lines of `glyph` in the GC's foreground color, in
+    /// the cell at (x, y).
+    unsafe fn draw_foreground(&self, glyph: &Glyph, x: i32, y: i32) {
+        let xlib = self.xlib;
+        let (part_width, part_height) = (self.atlas.part_width(), self.atlas.part_height());
+        let bold_offset = if glyph.attributes.contains(Attributes::BOLD) { self.atlas.bold_offset() } else { 0 };
+        let bands = if glyph.attributes.contains(Attributes::ITALIC) {
+            italic_bands(self.atlas)
+        } else {
+            vec![(0..part_height, 0)]
+        };
+
+        // Parts are drawn by stippling the foreground through them, with the
+        // stipple origin moved so the part lines up with where it goes.
+        (xlib.XSetFillStyle)(self.display, self.gc, xlib::FillStippled);
+        for part in self.atlas.parts(glyph.character) {
+            for &(ref rows, shift) in &bands {
+                for left in (shift..shift + bold_offset + 1).filter(|&left| left < part_width) {
+                    (xlib.XSetTSOrigin)(self.display, self.gc, x + left as i32, y - (part_height*part) as i32);
+                    (xlib.XFillRectangle)(self.display, self.pixmap, self.gc, x + left as i32, y + rows.start as i32,
+                                          part_width - left, rows.end - rows.start);
+                }
+            }
+        }
+        (xlib.XSetFillStyle)(self.display, self.gc, xlib::FillSolid);
+
+        for rows in self.atlas.lines(glyph.attributes) {
+            (xlib.XFillRectangle)(self.display, self.pixmap, self.gc, x, y + rows.start as i32,
+                                  part_width, rows.end - rows.start);
+        }
+    }
+}
+
+/// The rows of an italic glyph that are shifted right by the same amount,
+/// with that amount.
+fn italic_bands(atlas: &GlyphAtlas) -> Vec<(Range<u32>, u32)> {
+    let mut bands: Vec<(Range<u32>, u32)> = Vec::new();
+    for y in 0..atlas.part_height() {
+        let shift = atlas.italic_shift(y);
+        match bands.last_mut() {
+            Some(&mut (ref mut rows, last)) if last == shift => { rows.end = y + 1; continue; }
+            _ => {}
+        }
+        bands.push((y..y + 1, shift));
+    }
+    bands
+}
+
+impl<'a> Renderer for BackBuffer<'a> {
+    fn cell_size(&self) -> (u32, u32) {
+        (self.atlas.part_width(), self.atlas.part_height())
+    }
+
+    unsafe fn draw_cells(&mut self, screen: &Screen, look: &Look, cells: &[(u32, u32)]) {
+        if self.pixmap == 0 {
+            return;
+        }
+        let xlib = self.xlib;
+        let (part_width, part_height) = self.cell_size();
+        let cells = drawn_cells(screen, self.cell_size(), cells);
+        for ((foreground, background), group) in group_by_colors(screen, look, &cells) {
+            // The backgrounds all in one request...
+            let mut rects: Vec<xlib::XRectangle> = group.iter().map(|&(col, row)| xlib::XRectangle {
+                x: (col * part_width) as libc::c_short,
+                y: (row * part_height) as libc::c_short,
+                width: part_width as libc::c_ushort,
+                height: part_height as libc::c_ushort,
+            }).collect();
+            (xlib.XSetForeground)(self.display, self.gc, self.pixels.pixel(background));
+            (xlib.XFillRectangles)(self.display, self.pixmap, self.gc, rects.as_mut_ptr(), rects.len() as libc::c_int);
+
+            // ...then each glyph over them.
+            if foreground == background {
+                continue;
+            }
+            (xlib.XSetForeground)(self.display, self.gc, self.pixels.pixel(foreground));
+            for (col, row) in group {
+                let (x, y) = ((col * part_width) as i32, (row * part_height) as i32);
+                self.draw_foreground(&look.glyph(screen, col, row), x, y);
+                if let Some((columns, rows)) = look.cursor_line(col, row).and_then(|shape| self.atlas.cursor_rect(shape)) {
+                    (xlib.XFillRectangle)(self.display, self.pixmap, self.gc, x + columns.start as i32, y + rows.start as i32,
+                                          columns.end - columns.start, rows.end - rows.start);
+                }
+            }
+        }
+    }
+
+    unsafe fn draw_screen(&mut self, screen: &Screen, look: &Look) {
+        self.fit(screen);
+        self.draw_cells(screen, look, &all_cells(screen));
+        if let (Some((x, y, width, height)), true) = (unfilled_rect(screen, self.cell_size()), self.pixmap != 0) {
+            (self.xlib.XSetForeground)(self.display, self.gc, self.pixels.pixel(0xffffff));
+            (self.xlib.XFillRectangle)(self.display, self.pixmap, self.gc, x as i32, y as i32, width, height);
+        }
+    }
+
+    unsafe fn copy_to_window(&mut self, x: i32, y: i32, width: u32, height: u32) {
+        let shown = clear_outside(self.xlib, self.display, self.window, self.size, (x, y, width, height));
+        if let (Some((x, y, width, height)), true) = (shown, self.pixmap != 0) {
+            (self.xlib.XCopyArea)(self.display, self.pixmap, self.window, self.gc, x, y, width, height, x, y);
+        }
+    }
+
+    unsafe fn free(&mut self) {
+        if self.pixmap != 0 {
+            (self.xlib.XFreePixmap)(self.display, self.pixmap);
+        }
+        (self.xlib.XFreePixmap)(self.display, self.glyph_bitmap);
+        (self.xlib.XFreeGC)(self.display, self.gc);
+        self.pixels.free();
+    }
+}
+
+/// The (columns, rows) of `screen` that are drawn. Pixel coordinates are
+/// 16-bit, so cells past `MAX_GRID_PIXELS` can't be; the grid size is kept
+/// within it, so only a screen larger than the grid loses any.
+fn drawn_grid(screen: &Screen, (cell_width, cell_height): (u32, u32)) -> (u32, u32) {
+    (screen.width.min(MAX_GRID_PIXELS / cell_width), screen.height().min(MAX_GRID_PIXELS / cell_height))
+}
+
+/// Size in pixels of the drawn part of `screen`.
+fn drawn_size(screen: &Screen, cell_size: (u32, u32)) -> (u32, u32) {
+    let (cols, rows) = drawn_grid(screen, cell_size);
+    (cols * cell_size.0, rows * cell_size.1)
+}
+
+/// Those of `cells` that are drawn.
+fn drawn_cells(screen: &Screen, cell_size: (u32, u32), cells: &[(u32, u32)]) -> Vec<(u32, u32)> {
+    let (cols, rows) = drawn_grid(screen, cell_size);
+    cells.iter().cloned().filter(|&(col, row)| col < cols && row < rows).collect()
+}
+
+/// The (x, y, width, height) in pixels of the part of a partial last row
+/// with no cells, which is drawn empty as in `headless::render_screen`.
+fn unfilled_rect(screen: &Screen, (cell_width, cell_height): (u32, u32)) -> Option<(u32, u32, u32, u32)> {
+    let filled = (screen.glyphs.len() as u32).checked_rem(screen.width).unwrap_or(0);
+    let (cols, rows) = drawn_grid(screen, (cell_width, cell_height));
+    if filled == 0 || filled >= cols || rows < screen.height() {
+        return None;
+    }
+    Some((filled * cell_width, (screen.height() - 1) * cell_height, (cols - filled) * cell_width, cell_height))
+}
+
+/// Every (column, row) of `screen`, including those of a partial last row.
+fn all_cells(screen: &Screen) -> Vec<(u32, u32)> {
+    if screen.width == 0 {
+        return Vec::new();
+    }
+    (0..screen.glyphs.len() as u32).map(|idx| (idx % screen.width, idx / screen.width)).collect()
+}
+
+/// Where a visual keeps red, green and blue in a pixel value.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct PixelFormat {
+    masks: [libc::c_ulong; 3],
+}
+
+impl PixelFormat {
+    fn new(red_mask: libc::c_ulong, green_mask: libc::c_ulong, blue_mask: libc::c_ulong) -> PixelFormat {
+        PixelFormat { masks: [red_mask, green_mask, blue_mask] }
+    }
+
+    /// The format of a visual of `class`, if pixel values can be computed
+    /// from its masks. That's only so on TrueColor: a DirectColor colormap
+    /// maps each channel through a writable table that may hold anything.
+    fn for_visual(class: libc::c_int, red_mask: libc::c_ulong, green_mask: libc::c_ulong,
+                  blue_mask: libc::c_ulong) -> Option<PixelFormat> {
+        if class == xlib::TrueColor {
+            Some(PixelFormat::new(red_mask, green_mask, blue_mask))
+        } else {
+            None
+        }
+    }
+
+    /// The pixel value for 0x00RRGGBB.
+    fn pixel(&self, rgb: u32) -> libc::c_ulong {
+        let channels = [rgb >> 16, rgb >> 8, rgb];
+        let mut pixel = 0;
+        for (&mask, &channel) in self.masks.iter().zip(channels.iter()) {
+            if mask == 0 {
+                continue;
+            }
+            let shift = mask.trailing_zeros();
+            let max = mask >> shift;
+            // Scale 0..=255 to 0..=max, rounding to the nearest.
+            pixel |= ((((channel & 0xff) as libc::c_ulong * max + 127) / 255) << shift) & mask;
+        }
+        pixel
+    }
+}
+
+/// Turns 0x00RRGGBB colors into pixel values for the default visual. They
+/// are computed from the visual's masks on TrueColor displays, and allocated
+/// from the default colormap on others, DirectColor included.
+struct Pixels<'a> {
+    xlib: &'a xlib::Xlib,
+    display: *mut xlib::Display,
+    screen_num: libc::c_int,
+    /// None if the visual has no masks.
+    format: Option<PixelFormat>,
+    colormap: xlib::Colormap,
+    /// Every color looked up in the colormap so far.
+    looked_up: HashMap<u32, libc::c_ulong>,
+    /// The colormap entries we allocated, to be freed.
+    allocated: Vec<libc::c_ulong>,
+}
+
+impl<'a> Pixels<'a> {
+    unsafe fn new(xlib: &'a xlib::Xlib, display: *mut xlib::Display, screen_num: libc::c_int) -> Pixels<'a> {
+        let visual = (xlib.XDefaultVisual)(display, screen_num);
+        let format = PixelFormat::for_visual((*visual).class, (*visual).red_mask, (*visual).green_mask, (*visual).blue_mask);
+        Pixels {
+            xlib: xlib,
+            display: display,
+            screen_num: screen_num,
+            format: format,
+            colormap: (xlib.XDefaultColormap)(display, screen_num),
+            looked_up: HashMap::new(),
+            allocated: Vec::new(),
+        }
+    }
+
+    unsafe fn pixel(&mut self, rgb: u32) -> libc::c_ulong {
+        if let Some(format) = self.format {
+            return format.pixel(rgb);
+        }
+        if let Some(&pixel) = self.looked_up.get(&rgb) {
+            return pixel;
+        }
+
+        let mut color: xlib::XColor = zeroed();
+        color.red = ((rgb >> 16) & 0xff) as u16 * 0x101;
+        color.green = ((rgb >> 8) & 0xff) as u16 * 0x101;
+        color.blue = (rgb & 0xff) as u16 * 0x101;
+        color.flags = xlib::DoRed | xlib::DoGreen | xlib::DoBlue;
+        let pixel = if (self.xlib.XAllocColor)(self.display, self.colormap, &mut color) != 0 {
+            self.allocated.push(color.pixel);
+            color.pixel
+        } else if (rgb >> 16 & 0xff) + (rgb >> 8 & 0xff) + (rgb & 0xff) >= 3 * 0x80 {
+            // The colormap is full. Settle for black or white.
+            (self.xlib.XWhitePixel)(self.display, self.screen_num)
+        } else {
+            (self.xlib.XBlackPixel)(self.display, self.screen_num)
+        };
+        self.looked_up.insert(rgb, pixel);
+        pixel
+    }
+
+    unsafe fn free(&mut self) {
+        if !self.allocated.is_empty() {
+            (self.xlib.XFreeColors)(self.display, self.colormap, self.allocated.as_mut_ptr(),
+                                    self.allocated.len() as libc::c_int, 0);
+        }
+    }
+}
+
+/// Packs a framebuffer pixel's red, green and blue into 0x00RRGGBB.
+fn rgba_to_rgb(rgba: [u8; 4]) -> u32 {
+    ((rgba[0] as u32) << 16) | ((rgba[1] as u32) << 8) | rgba[2] as u32
+}
+
+/// Writes the low `out.len()` bytes of `pixel` into `out` in the given
+/// byte order.
+fn pack_pixel(pixel: libc::c_ulong, msb_first: bool, out: &mut [u8]) {
+    let len = out.len();
+    for (i, byte) in out.iter_mut().enumerate() {
+        let shift = if msb_first { 8 * (len - 1 - i) } else { 8 * i };
+        *byte = (pixel >> shift) as u8;
+    }
+}
+
+/// Set if the X server refused an `XShmAttach`.
+static SHM_ATTACH_FAILED: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "C" fn note_shm_attach_error(_display: *mut xlib::Display, _event: *mut xlib::XErrorEvent) -> libc::c_int {
+    SHM_ATTACH_FAILED.store(true, Ordering::SeqCst);
+    0
+}
+
+/// The screen rasterized in the client, the same way the headless backend
+/// does, into an XImage that changed rows are uploaded from. The image is
+/// shared with the X server through MIT-SHM when the server allows it.
+struct ImageBuffer<'a> {
+    xlib: &'a xlib::Xlib,
+    /// Only loaded if the server has MIT-SHM.
+    xext: Option<xshm::Xext>,
+    display: *mut xlib::Display,
+    window: xlib::Window,
+    gc: xlib::GC,
+    visual: *mut xlib::Visual,
+    depth: c_uint,
+    pixels: Pixels<'a>,
+    atlas: &'a GlyphAtlas,
+    framebuffer: Framebuffer,
+    /// Null until there's something to draw.
+    image: *mut xlib::XImage,
+    /// Set if `image` is in shared memory.
+    shm: Option<Box<xshm::XShmSegmentInfo>>,
+    /// Whether the server may still be reading the shared memory.
+    uploading: bool,
+}
+
+impl<'a> ImageBuffer<'a> {
+    unsafe fn new(xlib: &'a xlib::Xlib, display: *mut xlib::Display, window: xlib::Window, screen_num: libc::c_int,
+                  atlas: &'a GlyphAtlas) -> ImageBuffer<'a> {
+        let xext = xshm::Xext::open().ok().filter(|xext| (xext.XShmQueryExtension)(display) == xlib::True);
+        let visual = (xlib.XDefaultVisual)(display, screen_num);
+        ImageBuffer {
+            xlib: xlib,
+            xext: xext,
+            display: display,
+            window: window,
+            gc: (xlib.XCreateGC)(display, window, 0, null_mut()),
+            visual: visual,
+            depth: (xlib.XDefaultDepth)(display, screen_num) as c_uint,
+            pixels: Pixels::new(xlib, display, screen_num),
+            atlas: atlas,
+            framebuffer: Framebuffer::new(0, 0),
+            image: null_mut(),
+            shm: None,
+            uploading: false,
+        }
+    }
+
+    /// Makes the framebuffer and image fit `screen`. Their contents are
+    /// undefined afterwards.
+    unsafe fn fit(&mut self, screen: &Screen) {
+        let size = drawn_size(screen, self.cell_size());
+        if size == (self.framebuffer.width(), self.framebuffer.height()) {
+            return;
+        }
+        self.destroy_image();
+        self.framebuffer = Framebuffer::new(size.0, size.1);
+        if size.0 > 0 && size.1 > 0 {
+            self.image = match self.create_shm_image(size) {
+                Some(image) => image,
+                None => self.create_image(size),
+            };
+        }
+    }
+
+    /// An image in shared memory, or None if the server can't share it.
+    unsafe fn create_shm_image(&mut self, (width, height): (u32, u32)) -> Option<*mut xlib::XImage> {
+        let xlib = self.xlib;
+        let mut shm: Box<xshm::XShmSegmentInfo> = Box::new(zeroed());
+        let image = match self.xext {
+            Some(ref xext) => (xext.XShmCreateImage)(self.display, self.visual, self.depth, xlib::ZPixmap, null_mut(),
+                                                     &mut *shm, width, height),
+            None => { return None; }
+        };
+        if image.is_null() {
+            return None;
+        }
+
+        let bytes = (*image).bytes_per_line as usize * height as usize;
+        shm.shmid = libc::shmget(libc::IPC_PRIVATE, bytes, libc::IPC_CREAT | 0o600);
+        if shm.shmid < 0 {
+            (xlib.XDestroyImage)(image);
+            return None;
+        }
+        shm.shmaddr = libc::shmat(shm.shmid, null(), 0) as *mut libc::c_char;
+        if shm.shmaddr as isize == -1 {
+            libc::shmctl(shm.shmid, libc::IPC_RMID, null_mut());
+            (xlib.XDestroyImage)(image);
+            return None;
+        }
+        shm.readOnly = xlib::False;
+        (*image).data = shm.shmaddr;
+
+        // A remote server can claim MIT-SHM and then fail to attach, which
+        // is reported as an error rather than a return value.
+        (xlib.XSync)(self.display, xlib::False);
+        SHM_ATTACH_FAILED.store(false, Ordering::SeqCst);
+        let previous_handler = (xlib.XSetErrorHandler)(Some(note_shm_attach_error));
+        if let Some(ref xext) = self.xext {
+            (xext.XShmAttach)(self.display, &mut *shm);
+        }
+        (xlib.XSync)(self.display, xlib::False);
+        (xlib.XSetErrorHandler)(previous_handler);
+
+        // The segment now lasts only until both sides detach from it.
+        libc::shmctl(shm.shmid, libc::IPC_RMID, null_mut());
+
+        if SHM_ATTACH_FAILED.load(Ordering::SeqCst) {
+            (*image).data = null_mut();
+            (xlib.XDestroyImage)(image);
+            libc::shmdt(shm.shmaddr as *const libc::c_void);
+            // Don't try again on the next resize.
+            self.xext = None;
+            return None;
+        }
+        self.shm = Some(shm);
+        Some(image)
+    }
+
+    /// An image in ordinary memory, sent with every upload.
+    unsafe fn create_image(&self, (width, height): (u32, u32)) -> *mut xlib::XImage {
+        let image = (self.xlib.XCreateImage)(self.display, self.visual, self.depth, xlib::ZPixmap, 0, null_mut(),
+                                             width, height, 32, 0);
+        if image.is_null() {
+            return image;
+        }
+        (*image).data = libc::calloc((*image).bytes_per_line as usize * height as usize, 1) as *mut libc::c_char;
+        if (*image).data.is_null() {
+            (self.xlib.XDestroyImage)(image);
+            return null_mut();
+        }
+        image
+    }
+
+    unsafe fn destroy_image(&mut self) {
+        if self.image.is_null() {
+            return;
+        }
+        self.finish_upload();
+        match (self.shm.take(), &self.xext) {
+            (Some(mut shm), Some(xext)) => {
+                (xext.XShmDetach)(self.display, &mut *shm);
+                (self.xlib.XSync)(self.display, xlib::False);
+                (*self.image).data = null_mut();
+                (self.xlib.XDestroyImage)(self.image);
+                libc::shmdt(shm.shmaddr as *const libc::c_void);
+            }
+            _ => { (self.xlib.XDestroyImage)(self.image); }
+        }
+        self.image = null_mut();
+    }
+
+    /// Waits for the server to finish reading the shared memory, so it can
+    /// be drawn into again.
+    unsafe fn finish_upload(&mut self) {
+        if self.uploading {
+            (self.xlib.XSync)(self.display, xlib::False);
+            self.uploading = false;
+        }
+    }
+
+    /// Converts a rectangle of the framebuffer into the image's pixel format.
+    unsafe fn store_pixels(&mut self, left: u32, top: u32, width: u32, height: u32) {
+        let image = &mut *self.image;
+        let bytes_per_pixel = image.bits_per_pixel as usize / 8;
+        if image.bits_per_pixel % 8 != 0 || !(1..=4).contains(&bytes_per_pixel) {
+            // Odd layouts, like 4 bits per pixel, are left to Xlib.
+            for y in top..top + height {
+                for x in left..left + width {
+                    let pixel = self.pixels.pixel(rgba_to_rgb(self.framebuffer.pixel(x, y)));
+                    (self.xlib.XPutPixel)(image, x as libc::c_int, y as libc::c_int, pixel);
+                }
+            }
+            return;
+        }
+
+        let msb_first = image.byte_order == xlib::MSBFirst;
+        let stride = image.bytes_per_line as usize;
+        let data = slice::from_raw_parts_mut(image.data as *mut u8, stride * image.height as usize);
+        for y in top..top + height {
+            for x in left..left + width {
+                let offset = y as usize * stride + x as usize * bytes_per_pixel;
+                let pixel = self.pixels.pixel(rgba_to_rgb(self.framebuffer.pixel(x, y)));
+                pack_pixel(pixel, msb_first, &mut data[offset..offset + bytes_per_pixel]);
+            }
+        }
+    }
+}
+
+impl<'a> Renderer for ImageBuffer<'a> {
+    fn cell_size(&self) -> (u32, u32) {
+        (self.atlas.part_width(), self.atlas.part_height())
+    }
+
+    unsafe fn draw_cells(&mut self, screen: &Screen, look: &Look, cells: &[(u32, u32)]) {
+        if self.image.is_null() {
+            return;
+        }
+        self.finish_upload();
+        let (cell_width, cell_height) = self.cell_size();
+        for (col, row) in drawn_cells(screen, self.cell_size(), cells) {
+            let glyph = look.glyph(screen, col, row);
+            headless::draw_glyph(self.atlas, look.palette, &mut self.framebuffer, &glyph, col * cell_width, row * cell_height);
+            if let Some(shape) = look.cursor_line(col, row) {
+                headless::draw_cursor(self.atlas, look.palette, &mut self.framebuffer, shape, &glyph,
+                                      col * cell_width, row * cell_height);
+            }
+            self.store_pixels(col * cell_width, row * cell_height, cell_width, cell_height);
+        }
+    }
+
+    unsafe fn draw_screen(&mut self, screen: &Screen, look: &Look) {
+        self.fit(screen);
+        self.draw_cells(screen, look, &all_cells(screen));
+        if let (Some((x, y, width, height)), false) = (unfilled_rect(screen, self.cell_size()), self.image.is_null()) {
+            self.framebuffer.clear(x, y, width, height);
+            self.store_pixels(x, y, width, height);
+        }
+    }
+
+    unsafe fn copy_to_window(&mut self, x: i32, y: i32, width: u32, height: u32) {
+        let size = (self.framebuffer.width(), self.framebuffer.height());
+        let shown = clear_outside(self.xlib, self.display, self.window, size, (x, y, width, height));
+        let (x, y, width, height) = match shown {
+            Some(rect) if !self.image.is_null() => rect,
+            _ => { return; }
+        };
+        match (&self.shm, &self.xext) {
+            (Some(_), Some(xext)) => {
+                (xext.XShmPutImage)(self.display, self.window, self.gc, self.image, x, y, x, y, width, height, xlib::False);
+                self.uploading = true;
+            }
+            _ => { (self.xlib.XPutImage)(self.display, self.window, self.gc, self.image, x, y, x, y, width, height); }
+        }
+    }
+
+    /// Uploads each row holding damaged cells, from its first damaged cell
+    /// to its last.
+    unsafe fn copy_cells_to_window(&mut self, cells: &[(u32, u32)]) {
+        let mut spans: BTreeMap<u32, (u32, u32)> = BTreeMap::new();
+        for &(col, row) in cells {
+            let span = spans.entry(row).or_insert((col, col));
+            *span = (span.0.min(col), span.1.max(col));
+        }
+        let (cell_width, cell_height) = self.cell_size();
+        for (row, (first_col, last_col)) in spans {
+            self.copy_to_window((first_col * cell_width) as i32, (row * cell_height) as i32,
+                                (last_col + 1 - first_col) * cell_width, cell_height);
+        }
+    }
+
+    unsafe fn free(&mut self) {
+        self.destroy_image();
+        (self.xlib.XFreeGC)(self.display, self.gc);
+        self.pixels.free();
+    }
+}
+
+/// What `GridUi` sends the UI thread, which it checks when woken.
+struct Sources {
+    screens: Receiver<ScreenUpdate>,
+    titles: Receiver<String>,
+    palettes: Receiver<Palette>,
+    cursors: Receiver<Cursor>,
+}
+
+unsafe fn ui_main(signal_fd: libc::c_int, sources: Sources,
+                  input_event_sink: Sender<InputEvent>, grid_size: Arc<Mutex<(u32, u32)>>, config: GridUiConfig) {
     // Open Xlib library
     let xlib = xlib::Xlib::open().unwrap();
 
     // Open display
     let display = (xlib.XOpenDisplay)(null());
-    if display == null_mut() {
+    if display.is_null() {
       panic!("can't open display");
     }
 
@@ -111,14 +1027,18 @@ unsafe fn ui_main(signal_fd: libc::c_int, screen_source: Receiver<Screen>, input
     let mut attributes: xlib::XSetWindowAttributes = zeroed();
     attributes.background_pixel = white_pixel;
 
-    let window = (xlib.XCreateWindow)(display, root, 0, 0, DEFAULT_WIDTH, DEFAULT_HEIGHT, 0, 0,
+    let atlas = config.atlas();
+    let initial_size = *grid_size.lock().unwrap();
+    let window = (xlib.XCreateWindow)(display, root, 0, 0,
+                                      initial_size.0 * atlas.part_width(), initial_size.1 * atlas.part_height(), 0, 0,
                                       xlib::InputOutput as c_uint, null_mut(),
                                       xlib::CWBackPixel, &mut attributes);
-    (xlib.XSelectInput)(display, window, xlib::ExposureMask | xlib::ButtonPressMask | xlib::ButtonReleaseMask | xlib::KeyPressMask | xlib::KeyReleaseMask);
+    (xlib.XSelectInput)(display, window, xlib::ExposureMask | xlib::ButtonPressMask | xlib::ButtonReleaseMask | xlib::KeyPressMask | xlib::KeyReleaseMask |
+                                               xlib::PointerMotionMask | xlib::EnterWindowMask | xlib::LeaveWindowMask |
+                                               xlib::StructureNotifyMask);
 
-    // Set window title
-    let title_str = CString::new(TITLE).unwrap();
-    (xlib.XStoreName)(display, window, title_str.as_ptr() as *mut _);
+    set_title(&xlib, display, window, &config.title);
+    set_size_hints(&xlib, display, window, &config, atlas.part_width(), atlas.part_height());
 
     // Subscribe to delete (close) events
     let mut protocols = [wm_delete_window];
@@ -129,6 +1049,28 @@ unsafe fn ui_main(signal_fd: libc::c_int, screen_source: Receiver<Screen>, input
       panic!("can't set WM protocols");
     }
 
+    // Report held keys as repeated presses without releases in between, like
+    // Windows does.
+    (xlib.XkbSetDetectableAutoRepeat)(display, xlib::True, null_mut());
+
+    // Open an input method so typed text follows the keyboard layout, dead
+    // keys and compose sequences. Fall back to plain XLookupString without one.
+    libc::setlocale(libc::LC_CTYPE, b"\0".as_ptr() as *const libc::c_char);
+    (xlib.XSetLocaleModifiers)(b"\0".as_ptr() as *const libc::c_char);
+    let input_method = (xlib.XOpenIM)(display, null_mut(), null_mut(), null_mut());
+    let input_context = if input_method.is_null() {
+        null_mut()
+    } else {
+        (xlib.XCreateIC)(input_method,
+                         xlib::XNInputStyle_0.as_ptr(), (xlib::XIMPreeditNothing | xlib::XIMStatusNothing) as libc::c_ulong,
+                         xlib::XNClientWindow_0.as_ptr(), window,
+                         xlib::XNFocusWindow_0.as_ptr(), window,
+                         null_mut::<libc::c_void>())
+    };
+    if !input_context.is_null() {
+        (xlib.XSetICFocus)(input_context);
+    }
+
     // Show window
     (xlib.XMapWindow)(display, window);
 
@@ -137,15 +1079,27 @@ unsafe fn ui_main(signal_fd: libc::c_int, screen_source: Receiver<Screen>, input
 
 
     let connection_number = (xlib.XConnectionNumber)(display);
-    println!("Connection_number = {}", connection_number);
 
     let mut screen = Screen { glyphs: Vec::new(), width: 0 }; 
+    let mut palette = config.palette.clone();
+    let mut blink_visible = true;
+    let mut next_blink = Instant::now() + config.blink_interval;
+    let mut cursor = Cursor::hidden();
+    let mut cursor_blink_visible = true;
+    let mut next_cursor_blink = Instant::now();
+    let mut renderer: Box<dyn Renderer> = match config.rendering {
+        Rendering::Server => Box::new(BackBuffer::new(&xlib, display, window, screen_num, &atlas)),
+        Rendering::Client => Box::new(ImageBuffer::new(&xlib, display, window, screen_num, &atlas)),
+    };
+    let mut window_size = (initial_size.0 * atlas.part_width(), initial_size.1 * atlas.part_height());
+
+    let mouse_position = |x: libc::c_int, y: libc::c_int| MousePosition::from_pixels(x, y, atlas.part_width(), atlas.part_height());
 
-    let xs = include_bytes!("glyphs.bin");//[0x81u8, 0x42, 0x24, 0x18, 0x18, 0x24, 0x42, 0x81];
-    let bit_count = (xs.len() as u32) * 8;
-    let bits_per_glyph = 20*40;
-    let glyph_count = bit_count / bits_per_glyph;
-    let glyph_bitmap = (xlib.XCreateBitmapFromData)(display, window, xs.as_ptr() as *const i8, 20,40*glyph_count);
+    // Like the Windows backend, announce the size once at startup and then
+    // only when the number of whole cells changes.
+    let mut announced_grid_size = *grid_size.lock().unwrap();
+    let mut last_key_event = None;
+    input_event_sink.send(InputEvent::Size(announced_grid_size.0, announced_grid_size.1)).ok();
 
     'event_loop: loop {
       if (xlib.XPending)(display) == 0 {
@@ -161,8 +1115,44 @@ unsafe fn ui_main(signal_fd: libc::c_int, screen_source: Receiver<Screen>, input
                   revents: 0,
               },
           ];
-          poll(poll_fds.as_mut_ptr(), 2, -1);
-          let mut need_expose = false;
+          // Wake in time to blink, if anything on the screen does.
+          let blinking = screen.glyphs.iter().any(|glyph| glyph.attributes.contains(Attributes::BLINK));
+          let cursor_blinking = cursor.visible && cursor.blink_interval.is_some();
+          let mut deadlines = Vec::new();
+          if blinking {
+              deadlines.push(next_blink);
+          }
+          if cursor_blinking {
+              deadlines.push(next_cursor_blink);
+          }
+          let timeout = match deadlines.into_iter().min() {
+              Some(deadline) => {
+                  let wait = deadline.saturating_duration_since(Instant::now());
+                  (wait.as_millis() as libc::c_int).saturating_add(1)
+              }
+              None => -1,
+          };
+          poll(poll_fds.as_mut_ptr(), 2, timeout);
+          let mut redraw_all = false;
+          let mut damaged_cells = Vec::new();
+          // Cells the cursor left or moved to, which may be off the screen.
+          let mut cursor_cells = Vec::new();
+          if let (true, Some(interval)) = (cursor_blinking, cursor.blink_interval) {
+              if Instant::now() >= next_cursor_blink {
+                  cursor_blink_visible = !cursor_blink_visible;
+                  next_cursor_blink = Instant::now() + interval;
+                  cursor_cells.push((cursor.col, cursor.row));
+              }
+          }
+          if !blinking {
+              // Start the next blinking text off visible, for a full interval.
+              blink_visible = true;
+              next_blink = Instant::now() + config.blink_interval;
+          } else if Instant::now() >= next_blink {
+              blink_visible = !blink_visible;
+              next_blink = Instant::now() + config.blink_interval;
+              damaged_cells.extend(screen.blinking_cells());
+          }
           if poll_fds[0].revents != 0 {
               let mut buf = [0u8;10];
               loop {
@@ -172,34 +1162,65 @@ unsafe fn ui_main(signal_fd: libc::c_int, screen_source: Receiver<Screen>, input
               }
 
               loop {
-                  match screen_source.try_recv() {
+                  match sources.screens.try_recv() {
                       Err(TryRecvError::Empty) => { break; },
                       Err(TryRecvError::Disconnected) => { break 'event_loop; }
-                      Ok(new_screen) => { 
-                          screen = new_screen;
-                          println!("Screen = {:?}", screen.glyphs);
-                          need_expose = true;
+                      Ok(update) => {
+                          match screen.apply(update) {
+                              Damage::All => { redraw_all = true; }
+                              Damage::Cells(cells) => { damaged_cells.extend(cells); }
+                          }
                       }
                   }
               }
-          }
 
-          if need_expose {
-              let evt: xlib::XEvent = zeroed();
-              let mut expose_evt : xlib::XExposeEvent = evt.into();
-              expose_evt.type_ = xlib::Expose; 
-              expose_evt.width = 100;
-              expose_evt.width = 100;
-              expose_evt.display = display;
-              expose_evt.window = window;
+              while let Ok(title) = sources.titles.try_recv() {
+                  set_title(&xlib, display, window, &title);
+              }
+
+              while let Ok(new_palette) = sources.palettes.try_recv() {
+                  palette = new_palette;
+                  redraw_all = true;
+              }
+
+              while let Ok(new_cursor) = sources.cursors.try_recv() {
+                  cursor_cells.push((cursor.col, cursor.row));
+                  cursor = new_cursor;
+                  cursor_cells.push((cursor.col, cursor.row));
+                  // Start it off visible, so it is seen at once where it went.
+                  cursor_blink_visible = true;
+                  next_cursor_blink = Instant::now() + cursor.blink_interval.unwrap_or_default();
+              }
+          }
+          damaged_cells.extend(cursor_cells.into_iter().filter(|&(col, row)| screen.get(col, row).is_some()));
 
-              (xlib.XSendEvent)(display, window, xlib::False, xlib::ExposureMask, &mut expose_evt.into());
+          let look = Look {
+              palette: &palette,
+              blink_visible: blink_visible,
+              cursor: if cursor.visible && cursor_blink_visible { Some(cursor) } else { None },
+          };
+          if redraw_all {
+              renderer.draw_screen(&screen, &look);
+              renderer.copy_to_window(0, 0, window_size.0, window_size.1);
+              (xlib.XFlush)(display);
+          } else if !damaged_cells.is_empty() {
+              renderer.draw_cells(&screen, &look, &damaged_cells);
+              renderer.copy_cells_to_window(&damaged_cells);
+              (xlib.XFlush)(display);
           }
 
           continue;
       }
 
       (xlib.XNextEvent)(display, &mut event);
+      // Consumed by the input method, e.g. a dead key. Key events still
+      // report the physical key; only their text is left to the input method.
+      let filtered = (xlib.XFilterEvent)(&mut event, 0) == xlib::True;
+      let is_key = event.get_type() == xlib::KeyPress || event.get_type() == xlib::KeyRelease;
+      if filtered && !is_key {
+          continue;
+      }
+
       match event.get_type() {
         xlib::ClientMessage => {
           let xclient: xlib::XClientMessageEvent = From::from(event);
@@ -210,89 +1231,275 @@ unsafe fn ui_main(signal_fd: libc::c_int, screen_source: Receiver<Screen>, input
 
             // WM_DELETE_WINDOW (close event)
             if protocol == wm_delete_window {
+              input_event_sink.send(InputEvent::Close).ok();
               break;
             }
           }
         },
 
-        xlib::KeyPress => {
-            let pressevent: xlib::XKeyEvent = event.into();
-            println!("Key press! {}", pressevent.keycode);
+        xlib::KeyPress | xlib::KeyRelease => {
+            let mut key_event: xlib::XKeyEvent = event.into();
+
+            // Look the key up unshifted, so that a press and its release agree
+            // even if a modifier changed in between.
+            let keysym = (xlib.XkbKeycodeToKeysym)(display, key_event.keycode as u8, 0, 0);
+            // Input methods hand back keys they filtered but didn't use, so
+            // the same event can arrive twice.
+            let identity = (key_event.type_, key_event.keycode, key_event.time);
+            let repeated = last_key_event.replace(identity) == Some(identity);
+            if let (Some(key), false) = (keysym_to_key(keysym), repeated) {
+                let modifiers = state_to_modifiers(key_event.state);
+                let evt = if key_event.type_ == xlib::KeyPress {
+                    InputEvent::KeyDown(key, modifiers)
+                } else {
+                    InputEvent::KeyUp(key, modifiers)
+                };
+                input_event_sink.send(evt).ok();
+            }
+
+            if key_event.type_ == xlib::KeyPress && !filtered {
+                let text = lookup_text(&xlib, input_context, &mut key_event);
+                if !text.is_empty() {
+                    input_event_sink.send(InputEvent::TextInput(text)).ok();
+                }
+            }
+        }
+
+        xlib::ButtonPress | xlib::ButtonRelease => {
+            let button_event: xlib::XButtonEvent = event.into();
+            let position = mouse_position(button_event.x, button_event.y);
+            let modifiers = state_to_modifiers(button_event.state);
+
+            let evt = if let Some((horizontal, vertical)) = button_to_wheel(button_event.button) {
+                if button_event.type_ == xlib::ButtonRelease {
+                    continue;
+                }
+                InputEvent::MouseWheel(horizontal, vertical, position, modifiers)
+            } else if button_event.type_ == xlib::ButtonPress {
+                InputEvent::MouseDown(button_to_mouse_button(button_event.button), position, modifiers)
+            } else {
+                InputEvent::MouseUp(button_to_mouse_button(button_event.button), position, modifiers)
+            };
+            input_event_sink.send(evt).ok();
+        }
+
+        xlib::MotionNotify => {
+            let motion_event: xlib::XMotionEvent = event.into();
+            let evt = InputEvent::MouseMove(mouse_position(motion_event.x, motion_event.y), state_to_modifiers(motion_event.state));
+            input_event_sink.send(evt).ok();
+        }
+
+        xlib::EnterNotify => {
+            let crossing_event: xlib::XCrossingEvent = event.into();
+            input_event_sink.send(InputEvent::MouseEnter(mouse_position(crossing_event.x, crossing_event.y))).ok();
+        }
+
+        xlib::LeaveNotify => {
+            input_event_sink.send(InputEvent::MouseLeave).ok();
+        }
+
+        xlib::ConfigureNotify => {
+            let configure_event: xlib::XConfigureEvent = event.into();
+            window_size = (configure_event.width as u32, configure_event.height as u32);
+            // Window managers may ignore the size hints, but no more than
+            // the largest grid is drawn.
+            let largest = config.largest_grid_size();
+            let size = ((configure_event.width as u32 / atlas.part_width()).min(largest.0),
+                        (configure_event.height as u32 / atlas.part_height()).min(largest.1));
+            if size != announced_grid_size {
+                announced_grid_size = size;
+                *grid_size.lock().unwrap() = size;
+                input_event_sink.send(InputEvent::Size(size.0, size.1)).ok();
+            }
         }
 
         xlib::Expose => {
-          let gc = (xlib.XDefaultGC)(display, screen_num);
-/*          let p = (xlib.XCreatePixmap)(display, window, 100, 100, 24);
-          println!("p = {:?}, gc = {:?}", p, gc);
-          //(xlib.XPutPixel)(p, 0,0,0xff00ff);
-          let img = (xlib.XGetImage)(display, p, 0, 0, 10, 10, 0xffffffff, xlib::ZPixmap);
-          println!("img = {:?}  {}x{}", img, (*img).width, (*img).height);
-          println!("bytes_per_line = {}, depth = {}", (*img).bytes_per_line, (*img).depth);
-          println!("pixel initially = {:?}", (xlib.XGetPixel)(img, 0,0));
-          println!("masks = {:x}, {:x}, {:x}", (*img).red_mask, (*img).green_mask, (*img).blue_mask);
-          for x in (0..10) {
-              for y in (0..10) {
-                  (xlib.XPutPixel)(img, x,y, 0xd0ff0f);
-              }
-          }
-          println!("pixel finally = {:x}", (xlib.XGetPixel)(img, 0,0));
-
-
-          (xlib.XDrawLine)(display, p, gc, 0,0,40,40);
-
-          (xlib.XPutImage)(display, p, gc, img, 0,0, 20,20, 0,0);
-          (xlib.XCopyArea)(display, p, window, gc, 0,0, 100,100, 0,20);
-          (xlib.XCopyArea)(display, p, window, gc, 0,0, 100,100, 100,20);
-          (xlib.XFreePixmap)(display, p);
-*/
-          let mut x = 0u32;
-          let mut y = 0u32;
-          for glyph in screen.glyphs.iter() {
-              (xlib.XSetForeground)(display, gc, glyph.foreground as u64);
-              (xlib.XSetBackground)(display, gc, glyph.background as u64);
-
-              let parts = glyph_to_parts(glyph.character); 
-              println!("Drawing parts {:?}", parts);
-              (xlib.XCopyPlane)(display, glyph_bitmap, window, gc, 0,40*(parts[0] as i32), 20,40, (x as i32)*20,(y as i32)*40, 1);
-
-              x += 1;
-              if x==screen.width {
-                  x = 0;
-                  y += 1;
-              }
-          }
-//          (xlib.XDestroyImage)(img); 
+            let expose_event: xlib::XExposeEvent = event.into();
+            renderer.copy_to_window(expose_event.x, expose_event.y,
+                                    expose_event.width as c_uint, expose_event.height as c_uint);
         }
         _ => {},
       }
     }
 
     // Clean up
-    (xlib.XFreePixmap)(display, glyph_bitmap);
+    if !input_context.is_null() {
+        (xlib.XDestroyIC)(input_context);
+    }
+    if !input_method.is_null() {
+        (xlib.XCloseIM)(input_method);
+    }
+    renderer.free();
     (xlib.XDestroyWindow)(display, window);
     (xlib.XCloseDisplay)(display);
 }
 
 #[test]
+fn keysyms() {
+    assert_eq!(keysym_to_key(keysym::XK_a as libc::c_ulong), Some(Key::Character(0x1000)));
+    assert_eq!(keysym_to_key(keysym::XK_Q as libc::c_ulong), Some(Key::Character(0x1100)));
+    assert_eq!(keysym_to_key(keysym::XK_7 as libc::c_ulong), Some(Key::Character(17)));
+    assert_eq!(keysym_to_key(keysym::XK_space as libc::c_ulong), Some(Key::Character(0)));
+    assert_eq!(keysym_to_key(keysym::XK_Return as libc::c_ulong), Some(Key::Enter));
+    assert_eq!(keysym_to_key(keysym::XK_KP_Left as libc::c_ulong), Some(Key::Left));
+    assert_eq!(keysym_to_key(keysym::XK_F12 as libc::c_ulong), Some(Key::Function(12)));
+    assert_eq!(keysym_to_key(keysym::XK_Shift_L as libc::c_ulong), None);
+}
+
+#[test]
+fn modifier_state() {
+    assert!(state_to_modifiers(0).is_empty());
+    assert_eq!(state_to_modifiers(xlib::ShiftMask | xlib::LockMask), Modifiers::SHIFT);
+    assert_eq!(state_to_modifiers(xlib::ControlMask | xlib::Mod1Mask | xlib::Mod4Mask),
+               Modifiers::CONTROL | Modifiers::ALT | Modifiers::SUPER);
+}
+
+#[test]
+fn mouse_buttons() {
+    assert_eq!(button_to_mouse_button(1), MouseButton::Left);
+    assert_eq!(button_to_mouse_button(3), MouseButton::Right);
+    assert_eq!(button_to_mouse_button(8), MouseButton::Extra(1));
+    assert_eq!(button_to_mouse_button(9), MouseButton::Extra(2));
+    assert_eq!(button_to_wheel(4), Some((0, 1)));
+    assert_eq!(button_to_wheel(7), Some((1, 0)));
+    assert_eq!(button_to_wheel(2), None);
+}
+
+#[test]
+fn color_groups() {
+    use color::Color;
+    use screen::{Attributes, Glyph};
+
+    let glyph = |foreground, background| Glyph{ character: 0, foreground: Color::Rgb(foreground), background: background, attributes: Attributes::NONE };
+    let screen = Screen{
+        glyphs: vec![glyph(1, Color::Rgb(2)), glyph(3, Color::Rgb(4)), glyph(1, Color::Rgb(2)), glyph(1, Color::Indexed(2))],
+        width: 2,
+    };
+    let palette = Palette::new(vec![0, 0, 5]);
+    let look = Look { palette: &palette, blink_visible: true, cursor: None };
+    assert_eq!(group_by_colors(&screen, &look, &[(1, 1), (0, 0), (1, 0), (0, 1), (0, 0)]), vec![
+        ((1, 2), vec![(0, 0), (0, 1)]),
+        ((1, 5), vec![(1, 1)]),
+        ((3, 4), vec![(1, 0)]),
+    ]);
+}
+
+#[test]
+fn blinking_colors() {
+    use color::Color;
+
+    let glyph = Glyph{ character: 10, foreground: Color::Rgb(1), background: Color::Rgb(2),
+                       attributes: Attributes::BLINK | Attributes::INVERSE };
+    let screen = Screen{ glyphs: vec![glyph], width: 1 };
+    let palette = Palette::default();
+    let shown = Look { palette: &palette, blink_visible: true, cursor: None };
+    assert_eq!(group_by_colors(&screen, &shown, &[(0, 0)]), vec![((2, 1), vec![(0, 0)])]);
+    let hidden = Look { palette: &palette, blink_visible: false, cursor: None };
+    assert_eq!(group_by_colors(&screen, &hidden, &[(0, 0)]), vec![((1, 1), vec![(0, 0)])]);
+}
+
+#[test]
+fn cursor_colors() {
+    use color::Color;
+
+    let glyph = Glyph{ character: 10, foreground: Color::Rgb(1), background: Color::Rgb(2), attributes: Attributes::NONE };
+    let screen = Screen{ glyphs: vec![glyph, glyph], width: 2 };
+    let palette = Palette::default();
+    let block = Look { palette: &palette, blink_visible: true, cursor: Some(Cursor::new(1, 0)) };
+    assert_eq!(group_by_colors(&screen, &block, &[(0, 0), (1, 0)]), vec![((1, 2), vec![(0, 0)]), ((2, 1), vec![(1, 0)])]);
+    assert_eq!(block.cursor_line(1, 0), None);
+
+    let bar = Look { palette: &palette, blink_visible: true, cursor: Some(Cursor::new(1, 0).shape(CursorShape::Bar)) };
+    assert_eq!(group_by_colors(&screen, &bar, &[(0, 0), (1, 0)]), vec![((1, 2), vec![(0, 0), (1, 0)])]);
+    assert_eq!((bar.cursor_line(0, 0), bar.cursor_line(1, 0)), (None, Some(CursorShape::Bar)));
+}
+
+#[test]
+fn partial_rows() {
+    use color::Color;
+
+    let glyph = Glyph{ character: 0, foreground: Color::BLACK, background: Color::WHITE, attributes: Attributes::NONE };
+    let screen = Screen{ glyphs: vec![glyph; 5], width: 3 };
+    assert_eq!(all_cells(&screen), vec![(0, 0), (1, 0), (2, 0), (0, 1), (1, 1)]);
+    assert_eq!(unfilled_rect(&screen, (20, 40)), Some((40, 40, 20, 40)));
+    assert_eq!(unfilled_rect(&Screen::new(3, 2, glyph), (20, 40)), None);
+    assert_eq!(unfilled_rect(&Screen{ glyphs: Vec::new(), width: 0 }, (20, 40)), None);
+}
+
+#[test]
+fn pixel_limit() {
+    use color::Color;
+
+    let glyph = Glyph{ character: 0, foreground: Color::BLACK, background: Color::WHITE, attributes: Attributes::NONE };
+    // 40x80 cells: 819 columns and 409 rows fit in 16-bit coordinates.
+    let screen = Screen::new(1000, 500, glyph);
+    assert_eq!(drawn_grid(&screen, (40, 80)), (819, 409));
+    assert_eq!(drawn_size(&screen, (40, 80)), (32760, 32720));
+    assert_eq!(drawn_cells(&screen, (40, 80), &[(0, 0), (818, 408), (819, 0), (0, 409)]), vec![(0, 0), (818, 408)]);
+    assert_eq!(drawn_grid(&screen, (20, 40)), (1000, 500));
+
+    let mut partial = Screen::new(1000, 1, glyph);
+    partial.glyphs.truncate(900);
+    assert_eq!(unfilled_rect(&partial, (20, 40)), Some((18000, 0, 2000, 40)));
+    assert_eq!(unfilled_rect(&partial, (40, 80)), None);
+    partial.glyphs.truncate(500);
+    assert_eq!(unfilled_rect(&partial, (40, 80)), Some((20000, 0, 12760, 80)));
+}
+
+#[test]
+fn italic_rows() {
+    let bands = italic_bands(&GlyphAtlas::builtin());
+    assert_eq!(bands.first(), Some(&(0..10, 3)));
+    assert_eq!(bands.last(), Some(&(30..40, 0)));
+    assert_eq!(bands.iter().map(|band| band.0.end - band.0.start).sum::<u32>(), 40);
+}
+
+#[test]
+fn pixel_formats() {
+    let rgb = PixelFormat::new(0xff0000, 0xff00, 0xff);
+    assert_eq!(rgb.pixel(0x123456), 0x123456);
+    let bgr = PixelFormat::new(0xff, 0xff00, 0xff0000);
+    assert_eq!(bgr.pixel(0x123456), 0x563412);
+    let rgb565 = PixelFormat::new(0xf800, 0x7e0, 0x1f);
+    assert_eq!(rgb565.pixel(0xff8000), 0xfc00);
+    assert_eq!(rgb565.pixel(0xffffff), 0xffff);
+    assert_eq!(PixelFormat::for_visual(xlib::TrueColor, 0xff0000, 0xff00, 0xff), Some(rgb));
+    assert_eq!(PixelFormat::for_visual(xlib::DirectColor, 0xff0000, 0xff00, 0xff), None);
+    assert_eq!(PixelFormat::for_visual(xlib::PseudoColor, 0, 0, 0), None);
+    assert_eq!(rgba_to_rgb([0x12, 0x34, 0x56, 0xff]), 0x123456);
+
+    let mut bytes = [0u8; 4];
+    pack_pixel(0x123456, false, &mut bytes);
+    assert_eq!(bytes, [0x56, 0x34, 0x12, 0x00]);
+    pack_pixel(0x123456, true, &mut bytes[..3]);
+    assert_eq!(bytes[..3], [0x12, 0x34, 0x56]);
+}
+
+#[test]
+#[ignore] // Needs an X display, and never finishes.
 fn x11test() {
-    let mut gridui = GridUi::new();
+    use color::Color;
+    use screen::{Attributes, Glyph};
+    use std::time::Duration;
+
+    let gridui = GridUi::default();
 
 
     let mut i = 1;
     loop {
-        i = i + 1;
+        i += 1;
         if i==10 { i=1; }
         let screen = Screen{
             glyphs: vec![ 
-                Glyph{ character: i, foreground: 0xff, background: 0xff00 },
-                Glyph{ character: i+1, foreground: 0, background: 0xffffff },
+                Glyph{ character: i, foreground: Color::Rgb(0xff), background: Color::Rgb(0xff00), attributes: Attributes::NONE },
+                Glyph{ character: i+1, foreground: Color::BLACK, background: Color::WHITE, attributes: Attributes::NONE },
 
             ],
             width: 2,
         };
         gridui.send_screen(screen);
 
-        thread::sleep_ms(1000);
+        thread::sleep(Duration::from_millis(1000));
     }
 }
 